@@ -0,0 +1,1115 @@
+// CPU visible registers, see https://www.nesdev.org/wiki/APU_registers
+pub const PULSE1_CONTROL: u16 = 0x4000;
+pub const PULSE1_SWEEP: u16 = 0x4001;
+pub const PULSE1_TIMER_LOW: u16 = 0x4002;
+pub const PULSE1_TIMER_HIGH: u16 = 0x4003;
+pub const PULSE2_CONTROL: u16 = 0x4004;
+pub const PULSE2_SWEEP: u16 = 0x4005;
+pub const PULSE2_TIMER_LOW: u16 = 0x4006;
+pub const PULSE2_TIMER_HIGH: u16 = 0x4007;
+pub const TRIANGLE_CONTROL: u16 = 0x4008;
+pub const TRIANGLE_TIMER_LOW: u16 = 0x400A;
+pub const TRIANGLE_TIMER_HIGH: u16 = 0x400B;
+pub const NOISE_CONTROL: u16 = 0x400C;
+pub const NOISE_PERIOD: u16 = 0x400E;
+pub const NOISE_LENGTH: u16 = 0x400F;
+pub const DMC_CONTROL: u16 = 0x4010;
+pub const DMC_LOAD: u16 = 0x4011;
+pub const DMC_ADDRESS: u16 = 0x4012;
+pub const DMC_LENGTH: u16 = 0x4013;
+pub const STATUS: u16 = 0x4015;
+// Write only, reads of 0x4017 go to the second controller port
+pub const FRAME_COUNTER: u16 = 0x4017;
+
+use std::collections::VecDeque;
+
+use crate::audio::{Resampler, DEFAULT_SAMPLE_RATE};
+
+// NTSC CPU clock in Hz, the APU runs off the same cycles
+pub const CPU_CLOCK: u64 = 1_789_773;
+
+// Samples nobody drains are kept for this long, older ones are dropped
+const MAX_BUFFERED_SECONDS: u64 = 1;
+
+// Frame sequencer steps in CPU cycles, quarter frames clock envelopes and
+// the linear counter, half frames also clock length counters and sweeps
+const QUARTER_FRAME_1: u64 = 7457;
+const HALF_FRAME_1: u64 = 14913;
+const QUARTER_FRAME_3: u64 = 22371;
+const HALF_FRAME_2: u64 = 29829;
+const FRAME_SEQUENCE_LENGTH: u64 = 29830;
+// 5 step mode inserts an idle step before the last half frame
+const HALF_FRAME_2_5_STEP: u64 = 37281;
+const FRAME_SEQUENCE_LENGTH_5_STEP: u64 = 37282;
+
+// Loaded into length counters by the top 5 bits of the timer high write
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// 12.5%, 25%, 50% and 25% negated
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// Noise timer periods in APU cycles, NTSC
+const NOISE_PERIOD_TABLE: [u16; 16] = [
+    2, 4, 8, 16, 32, 48, 64, 80, 101, 127, 190, 254, 381, 508, 1017, 2034,
+];
+
+// DMC output periods in CPU cycles, NTSC
+const DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+// Sample addresses start here, 64 byte aligned
+const DMC_SAMPLES: u16 = 0xC000;
+
+// Sweeps that would go past the 11 bit timer silence the channel
+const MAX_TIMER_PERIOD: u16 = 0x7FF;
+// Periods this short are ultrasonic and silenced too
+const MIN_TIMER_PERIOD: u16 = 8;
+
+/// Volume of a channel, either constant or decaying from 15 to 0 at a
+/// rate set by the same 4 bits.
+#[derive(Default)]
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // Constant volume, or the divider period when decaying
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+
+    // Low 6 bits of the channel control register, --LC VVVV
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant = data & 0b0001_0000 != 0;
+        self.volume = data & 0b0000_1111;
+    }
+
+    fn restart(&mut self) {
+        self.start = true;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+            return;
+        }
+
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+
+        self.divider = self.volume;
+        if self.decay > 0 {
+            self.decay -= 1;
+        } else if self.looping {
+            self.decay = 15;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        return match self.constant {
+            true => self.volume,
+            false => self.decay,
+        };
+    }
+}
+
+/// Silences a channel after a set number of half frames unless halted.
+#[derive(Default)]
+pub struct LengthCounter {
+    enabled: bool,
+    pub halt: bool,
+    pub counter: u8,
+}
+
+impl LengthCounter {
+
+    // Disabling clears the counter, loads are ignored until enabled again
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1F) as usize];
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        return self.counter > 0;
+    }
+}
+
+/// Periodically bends a pulse channel's period up or down.
+#[derive(Default)]
+pub struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    reload: bool,
+    divider: u8,
+    // Pulse 1 negates with ones' complement, subtracting one more
+    ones_complement: bool,
+}
+
+impl Sweep {
+
+    // EPPP NSSS
+    fn write(&mut self, data: u8) {
+        self.enabled = data & 0b1000_0000 != 0;
+        self.period = (data >> 4) & 0b111;
+        self.negate = data & 0b0000_1000 != 0;
+        self.shift = data & 0b0000_0111;
+        self.reload = true;
+    }
+
+    // Continuously computed, even while disabled, as it also mutes
+    pub fn target_period(&self, period: u16) -> u16 {
+        let change = period >> self.shift;
+        if !self.negate {
+            return period + change;
+        }
+
+        return match self.ones_complement {
+            true => period.saturating_sub(change + 1),
+            false => period.saturating_sub(change),
+        };
+    }
+
+    pub fn mutes(&self, period: u16) -> bool {
+        return period < MIN_TIMER_PERIOD || self.target_period(period) > MAX_TIMER_PERIOD;
+    }
+
+    fn clock(&mut self, period: &mut u16) {
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.mutes(*period) {
+            *period = self.target_period(*period);
+        }
+
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+    }
+}
+
+/// Square wave channel, two of these make up the APU's melody voices.
+#[derive(Default)]
+pub struct Pulse {
+    duty: u8,
+    sequence: u8,
+    // 11 bit timer, clocked every APU cycle (2 CPU cycles)
+    pub period: u16,
+    timer: u16,
+    pub envelope: Envelope,
+    pub sweep: Sweep,
+    pub length: LengthCounter,
+}
+
+impl Pulse {
+
+    pub fn new(ones_complement: bool) -> Self {
+        let mut pulse = Pulse::default();
+        pulse.sweep.ones_complement = ones_complement;
+        return pulse;
+    }
+
+    // Registers relative to the channel, 0 - 3
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            // DDLC VVVV
+            PULSE1_CONTROL | PULSE2_CONTROL => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0b0010_0000 != 0;
+                self.envelope.write(data);
+            }
+            PULSE1_SWEEP | PULSE2_SWEEP => self.sweep.write(data),
+            PULSE1_TIMER_LOW | PULSE2_TIMER_LOW => self.period = (self.period & 0x700) | data as u16,
+            // LLLL LHHH
+            _ => {
+                self.period = (self.period & 0x0FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.envelope.restart();
+                self.sequence = 0;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period;
+        self.sequence = (self.sequence + 1) & 0b111;
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.length.clock();
+        self.sweep.clock(&mut self.period);
+    }
+
+    // 0 - 15
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.sweep.mutes(self.period) {
+            return 0;
+        }
+
+        return match DUTY_TABLE[self.duty as usize][self.sequence as usize] {
+            0 => 0,
+            _ => self.envelope.output(),
+        };
+    }
+}
+
+/// Triangle channel, a 32 step ramp gated by both a length counter and a
+/// finer grained linear counter.
+#[derive(Default)]
+pub struct Triangle {
+    sequence: u8,
+    // 11 bit timer, clocked every CPU cycle
+    pub period: u16,
+    timer: u16,
+    // Also halts the length counter
+    control: bool,
+    linear_reload_value: u8,
+    linear_reload: bool,
+    pub linear_counter: u8,
+    pub length: LengthCounter,
+}
+
+impl Triangle {
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            // CRRR RRRR
+            TRIANGLE_CONTROL => {
+                self.control = data & 0b1000_0000 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = data & 0b0111_1111;
+            }
+            TRIANGLE_TIMER_LOW => self.period = (self.period & 0x700) | data as u16,
+            // LLLL LHHH
+            TRIANGLE_TIMER_HIGH => {
+                self.period = (self.period & 0x0FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    // The sequencer only moves while both counters are running
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period;
+        if self.length.active() && self.linear_counter > 0 {
+            self.sequence = (self.sequence + 1) & 0b1_1111;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    // 15 down to 0 then back up, silencing only freezes it
+    pub fn output(&self) -> u8 {
+        return match self.sequence {
+            0 ..= 15 => 15 - self.sequence,
+            _ => self.sequence - 16,
+        };
+    }
+}
+
+/// Pseudo random noise from a 15 bit shift register.
+pub struct Noise {
+    // Short mode taps bit 6 instead of bit 1, giving a 93 step loop
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    pub shift_register: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Noise {
+
+    pub fn new() -> Self {
+        Noise {
+            short_mode: false,
+            period: NOISE_PERIOD_TABLE[0],
+            timer: 0,
+            // Loaded with 1 on power up
+            shift_register: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            // --LC VVVV
+            NOISE_CONTROL => {
+                self.length.halt = data & 0b0010_0000 != 0;
+                self.envelope.write(data);
+            }
+            // M--- PPPP
+            NOISE_PERIOD => {
+                self.short_mode = data & 0b1000_0000 != 0;
+                self.period = NOISE_PERIOD_TABLE[(data & 0b1111) as usize];
+            }
+            // LLLL L---
+            NOISE_LENGTH => {
+                self.length.load(data >> 3);
+                self.envelope.restart();
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period;
+        let tap = match self.short_mode {
+            true => 6,
+            false => 1,
+        };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    // 0 - 15
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift_register & 1 != 0 {
+            return 0;
+        }
+
+        return self.envelope.output();
+    }
+}
+
+/// Delta modulation channel, plays 1 bit deltas fetched from PRG through
+/// the CPU bus. Each fetch stalls the CPU.
+#[derive(Default)]
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    // 7 bit level, also directly loadable
+    pub level: u8,
+
+    sample_addr: u16,
+    sample_length: u16,
+    pub current_addr: u16,
+    pub bytes_remaining: u16,
+    // Fetched but not yet shifted out
+    sample_buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    pub irq: bool,
+}
+
+impl Dmc {
+
+    pub fn new() -> Self {
+        Dmc {
+            period: DMC_RATE_TABLE[0],
+            bits_remaining: 8,
+            silence: true,
+            ..Default::default()
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            // IL-- RRRR
+            DMC_CONTROL => {
+                self.irq_enabled = data & 0b1000_0000 != 0;
+                self.looping = data & 0b0100_0000 != 0;
+                self.period = DMC_RATE_TABLE[(data & 0b1111) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            // -DDD DDDD
+            DMC_LOAD => self.level = data & 0b0111_1111,
+            // Address %11AA AAAA AA00 0000
+            DMC_ADDRESS => self.sample_addr = DMC_SAMPLES | (data as u16) << 6,
+            // Length %LLLL LLLL 0001
+            _ => self.sample_length = (data as u16) << 4 | 1,
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Address of the next sample byte, when the buffer needs refilling
+    pub fn sample_request(&self) -> Option<u16> {
+        return match self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            true => Some(self.current_addr),
+            false => None,
+        };
+    }
+
+    // Hands over the byte read for the last request
+    pub fn fill(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // Wraps around to the start of PRG rather than to 0x0000
+        self.current_addr = match self.current_addr {
+            0xFFFF => 0x8000,
+            addr => addr + 1,
+        };
+
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period - 1;
+
+        // Steps by 2 unless that would leave the 7 bit range
+        if !self.silence {
+            match self.shift_register & 1 {
+                1 if self.level <= 125 => self.level += 2,
+                0 if self.level >= 2 => self.level -= 2,
+                _ => {}
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    // 0 - 127
+    pub fn output(&self) -> u8 {
+        return self.level;
+    }
+}
+
+/// Audio processing unit, clocked off the CPU cycle counter. The mixed
+/// output is sampled into `samples` for a frontend to drain, only the
+/// most recent second is kept when it doesn't.
+pub struct APU {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+
+    // CPU cycles into the frame sequence
+    frame_cycle: u64,
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    pub frame_irq: bool,
+    // Pulse and noise timers only move on every other CPU cycle
+    odd_cycle: bool,
+
+    // Brings the output down to the audio device's rate
+    pub resampler: Resampler,
+    pub samples: VecDeque<f32>,
+}
+
+impl APU {
+
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_cycle: 0,
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            odd_cycle: false,
+            resampler: Resampler::new(DEFAULT_SAMPLE_RATE),
+            samples: VecDeque::new(),
+        }
+    }
+
+    // Everything is write only except for the status register
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        return match addr {
+            // ID-F DNT21
+            STATUS => {
+                (self.pulse1.length.active() as u8)
+                    | (self.pulse2.length.active() as u8) << 1
+                    | (self.triangle.length.active() as u8) << 2
+                    | (self.noise.length.active() as u8) << 3
+                    | ((self.dmc.bytes_remaining > 0) as u8) << 4
+                    | (self.frame_irq as u8) << 6
+                    | (self.dmc.irq as u8) << 7
+            }
+            _ => 0,
+        };
+    }
+
+    // Reading the status acknowledges the frame IRQ
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        let data = self.peek_register(addr);
+        if addr == STATUS {
+            self.frame_irq = false;
+        }
+
+        return data;
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            PULSE1_CONTROL ..= PULSE1_TIMER_HIGH => {
+                self.pulse1.write_register(addr, data);
+            }
+            PULSE2_CONTROL ..= PULSE2_TIMER_HIGH => {
+                self.pulse2.write_register(addr, data);
+            }
+            TRIANGLE_CONTROL ..= TRIANGLE_TIMER_HIGH => {
+                self.triangle.write_register(addr, data);
+            }
+            NOISE_CONTROL ..= NOISE_LENGTH => {
+                self.noise.write_register(addr, data);
+            }
+            DMC_CONTROL ..= DMC_LENGTH => {
+                self.dmc.write_register(addr, data);
+            }
+            // ---D NT21
+            STATUS => {
+                self.pulse1.length.set_enabled(data & 0b0_0001 != 0);
+                self.pulse2.length.set_enabled(data & 0b0_0010 != 0);
+                self.triangle.length.set_enabled(data & 0b0_0100 != 0);
+                self.noise.length.set_enabled(data & 0b0_1000 != 0);
+                self.dmc.set_enabled(data & 0b1_0000 != 0);
+            }
+            // MI-- ----
+            FRAME_COUNTER => {
+                self.five_step_mode = data & 0b1000_0000 != 0;
+                self.irq_inhibit = data & 0b0100_0000 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                // The sequence restarts, 5 step mode clocks everything
+                // straight away
+                self.frame_cycle = 0;
+                if self.five_step_mode {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Level of the APU's IRQ output, held until acknowledged
+    pub fn irq(&self) -> bool {
+        return self.frame_irq || self.dmc.irq;
+    }
+
+    // Hands over everything sampled since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        return self.samples.drain(..).collect();
+    }
+
+    // DMC sample fetches are left to the caller, see `Dmc::sample_request`
+    pub fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.tick_cycle();
+        }
+    }
+
+    fn tick_cycle(&mut self) {
+        self.triangle.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+            self.noise.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        self.clock_frame_sequencer();
+
+        let level = self.output();
+        if let Some(sample) = self.resampler.push(level) {
+            if self.samples.len() as u64 >= self.resampler.rate * MAX_BUFFERED_SECONDS {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(sample);
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        self.frame_cycle += 1;
+
+        match (self.frame_cycle, self.five_step_mode) {
+            (QUARTER_FRAME_1, _) | (QUARTER_FRAME_3, _) => self.clock_quarter_frame(),
+            (HALF_FRAME_1, _) | (HALF_FRAME_2, false) | (HALF_FRAME_2_5_STEP, true) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                if self.frame_cycle == HALF_FRAME_2 && !self.irq_inhibit {
+                    self.frame_irq = true;
+                }
+            }
+            (FRAME_SEQUENCE_LENGTH, false) | (FRAME_SEQUENCE_LENGTH_5_STEP, true) => {
+                self.frame_cycle = 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    // Non linear mix, see https://www.nesdev.org/wiki/APU_Mixer
+    pub fn output(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse_out = match pulse == 0.0 {
+            true => 0.0,
+            false => 95.88 / (8128.0 / pulse + 100.0),
+        };
+
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output() as f32 / 22638.0;
+        let tnd_out = match tnd == 0.0 {
+            true => 0.0,
+            false => 159.79 / (1.0 / tnd + 100.0),
+        };
+
+        return pulse_out + tnd_out;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Frame IRQ inhibited so status only reflects the channels
+    fn enabled_apu() -> APU {
+        let mut apu = APU::new();
+        apu.write_register(FRAME_COUNTER, 0b0100_0000);
+        apu.write_register(STATUS, 0b1_1111);
+        return apu;
+    }
+
+    #[test]
+    fn test_length_counter() {
+        let mut apu = enabled_apu();
+        // Index 1 loads 254
+        apu.write_register(PULSE1_TIMER_HIGH, 0b0000_1000);
+        assert_eq!(apu.pulse1.length.counter, 254);
+        assert_eq!(apu.peek_register(STATUS), 0b01);
+
+        // Two half frames per sequence
+        apu.tick(FRAME_SEQUENCE_LENGTH);
+        assert_eq!(apu.pulse1.length.counter, 252);
+
+        // Halted counters hold
+        apu.write_register(PULSE1_CONTROL, 0b0010_0000);
+        apu.tick(FRAME_SEQUENCE_LENGTH);
+        assert_eq!(apu.pulse1.length.counter, 252);
+
+        // Disabling clears it
+        apu.write_register(STATUS, 0b10);
+        assert_eq!(apu.pulse1.length.counter, 0);
+        assert_eq!(apu.peek_register(STATUS), 0);
+    }
+
+    #[test]
+    fn test_length_counter_ignores_loads_while_disabled() {
+        let mut apu = APU::new();
+        apu.write_register(PULSE2_TIMER_HIGH, 0b0000_1000);
+        assert_eq!(apu.pulse2.length.counter, 0);
+    }
+
+    #[test]
+    fn test_envelope_decay() {
+        let mut apu = enabled_apu();
+        // Divider period 0, decay drops every quarter frame
+        apu.write_register(PULSE1_CONTROL, 0b0000_0000);
+        apu.write_register(PULSE1_TIMER_HIGH, 0b0000_1000);
+
+        apu.tick(QUARTER_FRAME_1);
+        assert_eq!(apu.pulse1.envelope.output(), 15);
+        apu.tick(HALF_FRAME_1 - QUARTER_FRAME_1);
+        assert_eq!(apu.pulse1.envelope.output(), 14);
+
+        // Constant volume ignores the decay
+        apu.write_register(PULSE1_CONTROL, 0b0001_0111);
+        assert_eq!(apu.pulse1.envelope.output(), 7);
+    }
+
+    #[test]
+    fn test_envelope_loop() {
+        let mut envelope = Envelope::default();
+        envelope.write(0b0010_0000);
+        envelope.restart();
+
+        for _ in 0..16 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+    }
+
+    #[test]
+    fn test_sweep_negate_ones_complement() {
+        let mut apu = enabled_apu();
+        // Negate with shift 1
+        apu.write_register(PULSE1_SWEEP, 0b1000_1001);
+        apu.write_register(PULSE2_SWEEP, 0b1000_1001);
+
+        // Pulse 1 subtracts one more than pulse 2
+        assert_eq!(apu.pulse1.sweep.target_period(0x100), 0x7F);
+        assert_eq!(apu.pulse2.sweep.target_period(0x100), 0x80);
+    }
+
+    #[test]
+    fn test_sweep_updates_period() {
+        let mut apu = enabled_apu();
+        // Enabled, divider period 0, shift 2
+        apu.write_register(PULSE2_SWEEP, 0b1000_0010);
+        apu.write_register(PULSE2_TIMER_LOW, 0x00);
+        apu.write_register(PULSE2_TIMER_HIGH, 0b0000_1001);
+
+        apu.tick(HALF_FRAME_1);
+        assert_eq!(apu.pulse2.period, 0x140);
+    }
+
+    #[test]
+    fn test_sweep_mutes() {
+        let mut apu = enabled_apu();
+        apu.write_register(PULSE1_CONTROL, 0b1101_1111);
+        apu.write_register(PULSE1_TIMER_HIGH, 0b0000_1000);
+
+        // Too short a period
+        apu.write_register(PULSE1_TIMER_LOW, 0x07);
+        assert!(apu.pulse1.sweep.mutes(apu.pulse1.period));
+
+        // Target overflowing 11 bits, even with the sweep disabled
+        apu.write_register(PULSE1_TIMER_LOW, 0xFF);
+        apu.write_register(PULSE1_TIMER_HIGH, 0b0000_1111);
+        assert!(apu.pulse1.sweep.mutes(apu.pulse1.period));
+        assert_eq!(apu.pulse1.output(), 0);
+    }
+
+    #[test]
+    fn test_duty_cycle() {
+        let mut apu = enabled_apu();
+        // 25% duty, constant volume 15, period 8
+        apu.write_register(PULSE1_CONTROL, 0b0101_1111);
+        apu.write_register(PULSE1_TIMER_LOW, 0x08);
+        apu.write_register(PULSE1_TIMER_HIGH, 0b0000_1000);
+
+        let mut waveform = vec![];
+        for _ in 0..8 {
+            waveform.push(apu.pulse1.output());
+            // 9 timer clocks per step, 2 CPU cycles each
+            apu.tick(18);
+        }
+        assert_eq!(waveform, vec![0, 15, 15, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut apu = enabled_apu();
+        apu.write_register(PULSE1_CONTROL, 0b1001_1111);
+        apu.write_register(PULSE1_TIMER_LOW, 0x40);
+        apu.write_register(PULSE1_TIMER_HIGH, 0b0000_1000);
+
+        // A second at 44.1 kHz
+        apu.tick(CPU_CLOCK);
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 44_100);
+        assert!(apu.samples.is_empty());
+
+        // Filtered around 0, with the pulse swinging both ways
+        assert!(samples.iter().all(|&sample| (-1.0..=1.0).contains(&sample)));
+        assert!(samples.iter().any(|&sample| sample > 0.05));
+        assert!(samples.iter().any(|&sample| sample < -0.05));
+    }
+
+    #[test]
+    fn test_samples_bounded() {
+        let mut apu = enabled_apu();
+        apu.write_register(PULSE1_CONTROL, 0b1001_1111);
+        apu.write_register(PULSE1_TIMER_LOW, 0x40);
+        apu.write_register(PULSE1_TIMER_HIGH, 0b0000_1000);
+
+        // Nothing drains them, only the last second is kept
+        apu.tick(5 * CPU_CLOCK);
+        assert_eq!(apu.samples.len() as u64, DEFAULT_SAMPLE_RATE * MAX_BUFFERED_SECONDS);
+        assert_eq!(apu.take_samples().len() as u64, DEFAULT_SAMPLE_RATE * MAX_BUFFERED_SECONDS);
+    }
+
+    #[test]
+    fn test_triangle_linear_counter() {
+        let mut apu = enabled_apu();
+        // Linear counter 2, period 0x20
+        apu.write_register(TRIANGLE_CONTROL, 0x02);
+        apu.write_register(TRIANGLE_TIMER_LOW, 0x20);
+        apu.write_register(TRIANGLE_TIMER_HIGH, 0b0000_1000);
+
+        apu.tick(QUARTER_FRAME_1);
+        assert_eq!(apu.triangle.linear_counter, 2);
+        apu.tick(0x100);
+        assert_ne!(apu.triangle.output(), 15);
+
+        // Two more quarter frames run it out, freezing the sequencer
+        apu.tick(QUARTER_FRAME_3 - QUARTER_FRAME_1 - 0x100);
+        assert_eq!(apu.triangle.linear_counter, 0);
+        let frozen = apu.triangle.output();
+        apu.tick(0x100);
+        assert_eq!(apu.triangle.output(), frozen);
+    }
+
+    #[test]
+    fn test_triangle_sequence() {
+        let mut triangle = Triangle::default();
+        let mut waveform = vec![];
+        for sequence in 0..32 {
+            triangle.sequence = sequence;
+            waveform.push(triangle.output());
+        }
+
+        assert_eq!(&waveform[..4], &[15, 14, 13, 12]);
+        assert_eq!(&waveform[14..18], &[1, 0, 0, 1]);
+        assert_eq!(waveform[31], 15);
+    }
+
+    #[test]
+    fn test_noise_lfsr() {
+        let mut noise = Noise::new();
+        noise.clock_timer();
+        // Bit 0 xor bit 1 of 1 feeds a 1 into bit 14
+        assert_eq!(noise.shift_register, 0x4000);
+
+        // Long mode repeats every 32767 steps, short mode every 93
+        for (mode, steps) in [(0x00, 32767), (0x80, 93)] {
+            let mut noise = Noise::new();
+            noise.write_register(NOISE_PERIOD, mode);
+            let start = noise.shift_register;
+            let mut count = 0;
+            loop {
+                noise.timer = 0;
+                noise.clock_timer();
+                count += 1;
+                if noise.shift_register == start {
+                    break;
+                }
+            }
+            assert_eq!(count, steps);
+        }
+    }
+
+    #[test]
+    fn test_noise_output() {
+        let mut apu = enabled_apu();
+        apu.write_register(NOISE_CONTROL, 0b0001_1001);
+        apu.write_register(NOISE_LENGTH, 0b0000_1000);
+
+        // Silent while bit 0 is set
+        apu.noise.shift_register = 0b10;
+        assert_eq!(apu.noise.output(), 9);
+        apu.noise.shift_register = 0b11;
+        assert_eq!(apu.noise.output(), 0);
+    }
+
+    #[test]
+    fn test_dmc_sample_playback() {
+        let mut apu = enabled_apu();
+        // Fastest rate, IRQ on, 17 bytes at 0xC040
+        apu.write_register(DMC_CONTROL, 0b1000_1111);
+        apu.write_register(DMC_LOAD, 0x40);
+        apu.write_register(DMC_ADDRESS, 0x01);
+        apu.write_register(DMC_LENGTH, 0x01);
+        apu.write_register(STATUS, 0b1_0000);
+
+        assert_eq!(apu.dmc.sample_request(), Some(0xC040));
+        assert_eq!(apu.peek_register(STATUS), 0b1_0000);
+
+        // All ones ramps the level up by 2 per bit
+        apu.dmc.fill(0xFF);
+        assert_eq!(apu.dmc.sample_request(), None);
+        apu.tick(54 * 16);
+        assert_eq!(apu.dmc.output(), 0x40 + 2 * 8);
+
+        for _ in 0..16 {
+            apu.dmc.fill(0x00);
+        }
+        assert_eq!(apu.dmc.bytes_remaining, 0);
+        assert!(apu.irq());
+        assert_eq!(apu.peek_register(STATUS), 0b1000_0000);
+
+        // Writing the status acknowledges it
+        apu.write_register(STATUS, 0);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_dmc_address_wraps_to_prg() {
+        let mut dmc = Dmc::new();
+        dmc.looping = true;
+        dmc.sample_addr = 0xFFFF;
+        dmc.sample_length = 2;
+        dmc.restart();
+
+        dmc.fill(0);
+        assert_eq!(dmc.current_addr, 0x8000);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = APU::new();
+        apu.tick(HALF_FRAME_2 - 1);
+        assert!(!apu.irq());
+        apu.tick(1);
+        assert!(apu.irq());
+        assert_eq!(apu.peek_register(STATUS) & 0b0100_0000, 0b0100_0000);
+
+        // Reading the status acknowledges it
+        apu.read_register(STATUS);
+        assert!(!apu.irq());
+
+        // Inhibited
+        apu.write_register(FRAME_COUNTER, 0b0100_0000);
+        apu.tick(FRAME_SEQUENCE_LENGTH);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_five_step_mode() {
+        let mut apu = enabled_apu();
+        apu.write_register(PULSE1_TIMER_HIGH, 0b0000_1000);
+
+        // Clocks a half frame straight away and never raises an IRQ
+        apu.write_register(FRAME_COUNTER, 0b1000_0000);
+        assert_eq!(apu.pulse1.length.counter, 253);
+
+        apu.tick(HALF_FRAME_2);
+        assert_eq!(apu.pulse1.length.counter, 252);
+        apu.tick(HALF_FRAME_2_5_STEP - HALF_FRAME_2);
+        assert_eq!(apu.pulse1.length.counter, 251);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_mixer() {
+        let mut apu = APU::new();
+        apu.triangle.sequence = 15;
+        assert_eq!(apu.output(), 0.0);
+
+        // Full scale on every channel comes close to 1
+        apu.dmc.level = 127;
+        apu.triangle.sequence = 0;
+        let tnd = apu.output();
+        assert!(tnd > 0.5 && tnd < 1.0);
+    }
+}
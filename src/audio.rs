@@ -0,0 +1,352 @@
+use std::f32::consts::PI;
+
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+
+use crate::apu::CPU_CLOCK;
+
+pub const DEFAULT_SAMPLE_RATE: u64 = 44_100;
+
+// How far the output rate may stray from nominal to steer the queue
+const MAX_RATE_DELTA: f64 = 0.005;
+// Latency the rate control aims for
+const TARGET_LATENCY_MS: u64 = 50;
+// Past this much queued audio new samples are dropped, rate control
+// can't pull back from emulation running flat out
+const MAX_LATENCY_MS: u64 = 200;
+
+/// First order high pass, the console's output is AC coupled.
+struct HighPass {
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl HighPass {
+
+    fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+
+        HighPass {
+            alpha: rc / (rc + dt),
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    fn filter(&mut self, input: f32) -> f32 {
+        let output = self.alpha * (self.previous_output + input - self.previous_input);
+        self.previous_input = input;
+        self.previous_output = output;
+        return output;
+    }
+}
+
+/// First order low pass.
+struct LowPass {
+    alpha: f32,
+    previous_output: f32,
+}
+
+impl LowPass {
+
+    fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+
+        LowPass {
+            alpha: dt / (rc + dt),
+            previous_output: 0.0,
+        }
+    }
+
+    fn filter(&mut self, input: f32) -> f32 {
+        self.previous_output += self.alpha * (input - self.previous_output);
+        return self.previous_output;
+    }
+}
+
+// Level changes are spread over this many output samples as band-limited
+// steps, half of them before the change
+const STEP_TAPS: usize = 32;
+// Positions between two output samples a change is rounded to
+const STEP_PHASES: usize = 256;
+// Passband edge as a fraction of the output rate, the windowed sinc has
+// rolled off by the time it gets to Nyquist
+const STEP_CUTOFF: f32 = 0.42;
+
+// Blackman windowed sinc, one row of taps per phase. Row p is for changes
+// p / STEP_PHASES of a sample before the first output they reach.
+fn step_kernel() -> Vec<[f32; STEP_TAPS]> {
+    return (0..=STEP_PHASES).map(|phase| {
+        let distance = phase as f32 / STEP_PHASES as f32;
+        let mut row = [0.0; STEP_TAPS];
+        for (tap, value) in row.iter_mut().enumerate() {
+            let x = tap as f32 + distance - (STEP_TAPS / 2) as f32;
+            let sinc = match x == 0.0 {
+                true => 1.0,
+                false => (PI * 2.0 * STEP_CUTOFF * x).sin() / (PI * 2.0 * STEP_CUTOFF * x),
+            };
+            let w = (tap as f32 + distance) / STEP_TAPS as f32;
+            let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+            *value = sinc * window;
+        }
+
+        // Every step has to settle on exactly its delta
+        let sum: f32 = row.iter().sum();
+        row.iter_mut().for_each(|value| *value /= sum);
+        return row;
+    }).collect();
+}
+
+/// Brings the APU's output, one level per CPU cycle, down to the audio
+/// device's rate. Level changes are added as band-limited steps, so
+/// nothing above Nyquist aliases back down, then go through the console's
+/// own output filters. Output lags by half a step, STEP_TAPS / 2 samples.
+pub struct Resampler {
+    // Output rate in Hz, nudged by rate control
+    pub rate: u64,
+    // Counts up by the output rate every cycle, a sample is due on
+    // passing the CPU clock
+    clock: u64,
+    // Level of the last cycle
+    level: f32,
+    kernel: Vec<[f32; STEP_TAPS]>,
+    // Parts of steps still to come out, pending[head] goes into the next
+    // sample
+    pending: [f32; STEP_TAPS],
+    head: usize,
+    // Sum of every step so far
+    output: f32,
+
+    high_pass_90: HighPass,
+    high_pass_440: HighPass,
+    low_pass_14k: LowPass,
+}
+
+impl Resampler {
+
+    pub fn new(rate: u64) -> Self {
+        let sample_rate = rate as f32;
+
+        Resampler {
+            rate,
+            clock: 0,
+            level: 0.0,
+            kernel: step_kernel(),
+            pending: [0.0; STEP_TAPS],
+            head: 0,
+            output: 0.0,
+            high_pass_90: HighPass::new(90.0, sample_rate),
+            high_pass_440: HighPass::new(440.0, sample_rate),
+            low_pass_14k: LowPass::new(14_000.0, sample_rate),
+        }
+    }
+
+    // Takes the level of a single CPU cycle, returns a sample when one
+    // is due
+    pub fn push(&mut self, level: f32) -> Option<f32> {
+        if level != self.level {
+            let delta = level - self.level;
+            self.level = level;
+
+            // How far ahead of the next output sample the change is
+            let phase = ((CPU_CLOCK - self.clock) * STEP_PHASES as u64 + CPU_CLOCK / 2) / CPU_CLOCK;
+            let row = &self.kernel[phase as usize];
+            for (tap, value) in row.iter().enumerate() {
+                self.pending[(self.head + tap) % STEP_TAPS] += delta * value;
+            }
+        }
+
+        self.clock += self.rate;
+        if self.clock < CPU_CLOCK {
+            return None;
+        }
+        self.clock -= CPU_CLOCK;
+
+        self.output += self.pending[self.head];
+        self.pending[self.head] = 0.0;
+        self.head = (self.head + 1) % STEP_TAPS;
+
+        let sample = self.high_pass_90.filter(self.output);
+        let sample = self.high_pass_440.filter(sample);
+        return Some(self.low_pass_14k.filter(sample));
+    }
+}
+
+/// Keeps the audio queue from running dry or piling up by resampling
+/// slightly faster or slower than nominal.
+pub struct RateControl {
+    base_rate: u64,
+    // Queued samples to hover around
+    pub target: usize,
+}
+
+impl RateControl {
+
+    pub fn new(base_rate: u64) -> Self {
+        RateControl {
+            base_rate,
+            target: (base_rate * TARGET_LATENCY_MS / 1000) as usize,
+        }
+    }
+
+    // Rate to resample at given how much is queued, above nominal when
+    // short of the target and below it when over
+    pub fn rate(&self, queued: usize) -> u64 {
+        let fill = queued as f64 / self.target as f64;
+        let delta = ((1.0 - fill) * MAX_RATE_DELTA).clamp(-MAX_RATE_DELTA, MAX_RATE_DELTA);
+        return (self.base_rate as f64 * (1.0 + delta)).round() as u64;
+    }
+}
+
+/// Where samples end up, a real device or nowhere.
+pub trait AudioOutput {
+    fn sample_rate(&self) -> u64;
+
+    fn queue(&mut self, samples: &[f32]);
+
+    // Samples waiting to be played
+    fn queued(&self) -> usize;
+}
+
+/// Discards everything, for headless runs and machines without audio.
+pub struct NullAudio {
+    pub sample_rate: u64,
+}
+
+impl AudioOutput for NullAudio {
+    fn sample_rate(&self) -> u64 {
+        return self.sample_rate;
+    }
+
+    fn queue(&mut self, _samples: &[f32]) {}
+
+    // Always exactly on target, leaves the rate at nominal
+    fn queued(&self) -> usize {
+        return RateControl::new(self.sample_rate).target;
+    }
+}
+
+/// Mono f32 SDL audio queue.
+pub struct SdlAudio {
+    queue: AudioQueue<f32>,
+    sample_rate: u64,
+}
+
+impl SdlAudio {
+
+    pub fn open(sdl_context: &sdl2::Sdl) -> Result<SdlAudio, String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired = AudioSpecDesired {
+            freq: Some(DEFAULT_SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: Some(1024),
+        };
+
+        // The device may settle on a different rate, e.g. 48 kHz
+        let queue = audio_subsystem.open_queue::<f32, _>(None, &desired)?;
+        let sample_rate = queue.spec().freq as u64;
+        queue.resume();
+
+        return Ok(SdlAudio { queue, sample_rate });
+    }
+}
+
+impl AudioOutput for SdlAudio {
+    fn sample_rate(&self) -> u64 {
+        return self.sample_rate;
+    }
+
+    fn queue(&mut self, samples: &[f32]) {
+        if self.queued() > (self.sample_rate * MAX_LATENCY_MS / 1000) as usize {
+            return;
+        }
+
+        if let Err(err) = self.queue.queue_audio(samples) {
+            eprintln!("audio: {}", err);
+        }
+    }
+
+    fn queued(&self) -> usize {
+        return self.queue.size() as usize / std::mem::size_of::<f32>();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resampler_rate() {
+        let mut resampler = Resampler::new(48_000);
+        let count = (0..CPU_CLOCK).filter_map(|_| resampler.push(0.0)).count();
+        assert_eq!(count, 48_000);
+    }
+
+    #[test]
+    fn test_resampler_removes_dc() {
+        let mut resampler = Resampler::new(DEFAULT_SAMPLE_RATE);
+        let samples: Vec<f32> = (0..CPU_CLOCK / 10).filter_map(|_| resampler.push(0.5)).collect();
+
+        // The step comes through half a step late, then decays away
+        assert!(samples[..STEP_TAPS].iter().any(|&sample| sample > 0.05));
+        assert!(samples.last().unwrap().abs() < 0.001);
+    }
+
+    #[test]
+    fn test_resampler_band_limits() {
+        // Alternating every cycle is far above anything audible
+        let mut resampler = Resampler::new(DEFAULT_SAMPLE_RATE);
+        let samples: Vec<f32> = (0..CPU_CLOCK / 10)
+            .filter_map(|cycle| resampler.push((cycle % 2) as f32))
+            .collect();
+
+        let peak = samples[100..].iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak < 0.05);
+    }
+
+    // RMS of a pulse wave at frequency once the filters have settled
+    fn pulse_rms(frequency: u64) -> f32 {
+        let mut resampler = Resampler::new(DEFAULT_SAMPLE_RATE);
+        let samples: Vec<f32> = (0..CPU_CLOCK / 2)
+            .filter_map(|cycle| resampler.push((cycle * frequency * 2 / CPU_CLOCK % 2) as f32))
+            .collect();
+
+        let settled = &samples[samples.len() / 2..];
+        return (settled.iter().map(|sample| sample * sample).sum::<f32>() / settled.len() as f32).sqrt();
+    }
+
+    #[test]
+    fn test_resampler_no_aliasing() {
+        // Just past the 22.05 kHz Nyquist, a box filter lets it alias
+        // down to 14.1 kHz
+        assert!(pulse_rms(30_000) < 0.01);
+
+        // Well inside the passband it comes through
+        assert!(pulse_rms(1_000) > 0.3);
+    }
+
+    #[test]
+    fn test_rate_control() {
+        let control = RateControl::new(48_000);
+        assert_eq!(control.target, 2400);
+
+        assert_eq!(control.rate(control.target), 48_000);
+        assert!(control.rate(control.target / 2) > 48_000);
+        assert!(control.rate(control.target * 3 / 2) < 48_000);
+
+        // Never strays more than half a percent
+        assert_eq!(control.rate(0), 48_240);
+        assert_eq!(control.rate(control.target * 10), 47_760);
+    }
+
+    #[test]
+    fn test_null_audio_keeps_nominal_rate() {
+        let mut audio = NullAudio { sample_rate: 48_000 };
+        audio.queue(&[0.0; 1000]);
+
+        let control = RateControl::new(audio.sample_rate());
+        assert_eq!(control.rate(audio.queued()), 48_000);
+    }
+}
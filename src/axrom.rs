@@ -0,0 +1,101 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bus_conflict, chr_memory, has_bus_conflicts, Mapper, PRG_ROM};
+
+const PRG_BANK_SIZE: usize = 32 * 1024;
+
+/// Mapper 7, a 32 KiB PRG bank and which nametable to show on every
+/// screen, both selected by writes to PRG space. CHR is 8 KiB of RAM.
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    bus_conflicts: bool,
+    // ---M -PPP
+    pub bank: u8,
+}
+
+impl Axrom {
+
+    pub fn new(rom: Rom) -> Self {
+        // Only AMROM has them, ANROM and AOROM don't
+        let bus_conflicts = has_bus_conflicts(&rom, false);
+        let (chr, chr_ram) = chr_memory(rom.chr_rom);
+
+        Axrom {
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_ram,
+            bus_conflicts,
+            bank: 0,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        if addr < PRG_ROM || self.prg_rom.is_empty() {
+            return 0;
+        }
+
+        let bank = (self.bank & 0b0111) as usize;
+        let offset = (addr - PRG_ROM) as usize;
+        return self.prg_rom[(bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()];
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= PRG_ROM {
+            self.bank = bus_conflict(data, self.cpu_peek(addr), self.bus_conflicts);
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        return self.chr[addr as usize % self.chr.len()];
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return match self.bank & 0b1_0000 {
+            0 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{numbered_banks, RomBuilder};
+
+    fn axrom(submapper: u8) -> Axrom {
+        return Axrom::new(RomBuilder::new(7)
+            .submapper(submapper)
+            .prg_rom(numbered_banks(4, PRG_BANK_SIZE))
+            .build());
+    }
+
+    #[test]
+    fn test_axrom() {
+        let mut axrom = axrom(0);
+        assert_eq!(axrom.cpu_peek(0x8000), 0);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+
+        axrom.cpu_write(0x8000, 0b1_0010);
+        assert_eq!(axrom.cpu_peek(0x8000), 2);
+        assert_eq!(axrom.cpu_peek(0xFFFF), 2);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_axrom_bus_conflicts() {
+        // None unless the submapper says so, bank 0 holds 0
+        let mut axrom = axrom(2);
+        axrom.cpu_write(0x8000, 0b1_0011);
+        assert_eq!(axrom.bank, 0);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::apu::{self, APU};
+use crate::cpu::Memory;
+use crate::gamepad::{self, Gamepad};
+use crate::mapper::{self, RamCartridge, SharedMapper};
+use crate::ppu::{OAMDATA, PPU};
+
+//  _______________ $10000  _______________
+// | PRG-ROM       |       |               |
+// | Upper Bank    |       |               |
+// |_ _ _ _ _ _ _ _| $C000 | PRG-ROM       |
+// | PRG-ROM       |       |               |
+// | Lower Bank    |       |               |
+// |_______________| $8000 |_______________|
+// | SRAM          |       | SRAM          |
+// |_______________| $6000 |_______________|
+// | Expansion ROM |       | Expansion ROM |
+// |_______________| $4020 |_______________|
+// | I/O Registers |       |               |
+// |_ _ _ _ _ _ _ _| $4000 |               |
+// | Mirrors       |       | I/O Registers |
+// | $2000-$2007   |       |               |
+// |_ _ _ _ _ _ _ _| $2008 |               |
+// | I/O Registers |       |               |
+// |_______________| $2000 |_______________|
+// | Mirrors       |       |               |
+// | $0000-$07FF   |       |               |
+// |_ _ _ _ _ _ _ _| $0800 |               |
+// | RAM           |       | RAM           |
+// |_ _ _ _ _ _ _ _| $0200 |               |
+// | Stack         |       |               |
+// |_ _ _ _ _ _ _ _| $0100 |               |
+// | Zero Page     |       |               |
+// |_______________| $0000 |_______________|
+
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+// Only 11 bits are wired up to the 2 KiB of internal RAM
+const RAM_MIRROR_MASK: u16 = 0b0000_0111_1111_1111;
+
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+// 8 registers repeated every 8 bytes
+const PPU_REGISTER_MASK: u16 = 0b0000_0000_0000_0111;
+
+const APU_IO_REGISTERS: u16 = 0x4000;
+const APU_IO_REGISTERS_END: u16 = 0x401F;
+// Channel registers, the rest of the range is I/O
+const APU_CHANNELS_END: u16 = 0x4013;
+// Writing page XX copies CPU memory XX00 - XXFF into OAM
+const OAM_DMA: u16 = 0x4014;
+
+// NTSC PPU runs 3 dots per CPU cycle
+const PPU_DOTS_PER_CYCLE: u64 = 3;
+// CPU cycles lost to every DMC sample fetch
+const DMC_STALL_CYCLES: u64 = 4;
+
+const CARTRIDGE_SPACE: u16 = 0x4020;
+const CARTRIDGE_SPACE_END: u16 = 0xFFFF;
+
+/// Sits between the CPU and everything it can address. Internal RAM is
+/// owned by the bus, every other range is routed to a pluggable handler.
+pub struct Bus {
+    cpu_vram: [u8; 2048],
+    // [0x2000 .. 0x3FFF] PPU registers
+    pub ppu: PPU,
+    // [0x4000 .. 0x4013], 0x4015 and writes to 0x4017 APU registers
+    pub apu: APU,
+    // 0x4016 and reads of 0x4017, controller ports 1 and 2
+    pub gamepads: [Gamepad; 2],
+    // [0x4000 .. 0x401F] Remaining I/O registers
+    pub io: Box<dyn Memory>,
+    // [0x4020 .. 0xFFFF] Cartridge space, the PPU shares the same board
+    pub cartridge: SharedMapper,
+    // Set by an OAM DMA, the CPU stalls until the copy is done
+    oam_dma: bool,
+    // Cycles the CPU spent stalled on DMC fetches
+    dmc_stall: u64,
+    // CPU cycle of the access being made, kept up to date by the CPU
+    pub cycle: u64,
+    // Last value on the data bus, read back by bits nothing drives. The
+    // CPU puts the address bytes of operands it fetched here itself.
+    pub open_bus: u8,
+}
+
+impl Bus {
+
+    pub fn new() -> Self {
+        // Until a real cartridge is plugged in cartridge space behaves
+        // like plain RAM so raw programs can still be loaded at 0x8000.
+        let cartridge = mapper::shared(RamCartridge::new());
+
+        Bus {
+            cpu_vram: [0; 2048],
+            ppu: PPU::with_cartridge(cartridge.clone()),
+            apu: APU::new(),
+            gamepads: [Gamepad::new(), Gamepad::new()],
+            io: Box::new(OpenBus),
+            cartridge,
+            oam_dma: false,
+            dmc_stall: 0,
+            cycle: 0,
+            open_bus: 0,
+        }
+    }
+
+    // Plugs a cartridge into both the CPU and PPU side, the PPU starts
+    // over from power on
+    pub fn insert_cartridge(&mut self, cartridge: SharedMapper) {
+        self.ppu = PPU::with_cartridge(cartridge.clone());
+        self.cartridge = cartridge;
+    }
+
+    // Takes the OAM DMA started by the last write, if there was one
+    pub fn take_oam_dma(&mut self) -> bool {
+        let oam_dma = self.oam_dma;
+        self.oam_dma = false;
+        return oam_dma;
+    }
+
+    // The copy happens all at once, the CPU accounts for the time it takes
+    fn run_oam_dma(&mut self, page: u8) {
+        let start = (page as u16) << 8;
+        for offset in 0..256 {
+            let data = self.mem_read(start + offset);
+            self.ppu.write_register(OAMDATA, data);
+        }
+
+        self.oam_dma = true;
+    }
+
+    // Takes the cycles DMC fetches stalled the CPU for since the last call,
+    // already ticked through the PPU and APU
+    pub fn take_dmc_stall(&mut self) -> u64 {
+        let stall = self.dmc_stall;
+        self.dmc_stall = 0;
+        return stall;
+    }
+
+    // Runs everything clocked off the CPU for the cycles it just spent
+    pub fn tick(&mut self, cycles: u64) {
+        let mut remaining = cycles;
+        while remaining > 0 {
+            remaining -= 1;
+            self.ppu.tick(PPU_DOTS_PER_CYCLE);
+            self.apu.tick(1);
+
+            // The DMC reads through the CPU bus, holding the CPU meanwhile
+            if let Some(addr) = self.apu.dmc.sample_request() {
+                let data = self.mem_read(addr);
+                self.apu.dmc.fill(data);
+                self.dmc_stall += DMC_STALL_CYCLES;
+                remaining += DMC_STALL_CYCLES;
+            }
+        }
+    }
+
+    // IRQ sources on the bus, wired together onto the CPU's IRQ line
+    pub fn irq(&self) -> bool {
+        return self.apu.irq() || self.cartridge.borrow().irq();
+    }
+}
+
+fn ppu_register(addr: u16) -> u16 {
+    return PPU_REGISTERS | (addr & PPU_REGISTER_MASK);
+}
+
+impl Memory for Bus {
+    fn mem_peek(&self, addr: u16) -> u8 {
+        return match addr {
+            RAM ..= RAM_MIRRORS_END => {
+                self.cpu_vram[(addr & RAM_MIRROR_MASK) as usize]
+            }
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.peek_register(ppu_register(addr))
+            }
+            APU_IO_REGISTERS ..= APU_CHANNELS_END | apu::STATUS => {
+                self.apu.peek_register(addr)
+            }
+            gamepad::JOYPAD1 => (self.open_bus & gamepad::OPEN_BUS_MASK) | self.gamepads[0].peek(),
+            gamepad::JOYPAD2 => (self.open_bus & gamepad::OPEN_BUS_MASK) | self.gamepads[1].peek(),
+            APU_IO_REGISTERS ..= APU_IO_REGISTERS_END => {
+                self.io.mem_peek(addr)
+            }
+            CARTRIDGE_SPACE ..= CARTRIDGE_SPACE_END => {
+                self.cartridge.borrow().cpu_peek(addr)
+            }
+        }
+    }
+
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = match addr {
+            RAM ..= RAM_MIRRORS_END => {
+                self.cpu_vram[(addr & RAM_MIRROR_MASK) as usize]
+            }
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.read_register(ppu_register(addr))
+            }
+            APU_IO_REGISTERS ..= APU_CHANNELS_END | apu::STATUS => {
+                self.apu.read_register(addr)
+            }
+            gamepad::JOYPAD1 => (self.open_bus & gamepad::OPEN_BUS_MASK) | self.gamepads[0].read(),
+            gamepad::JOYPAD2 => (self.open_bus & gamepad::OPEN_BUS_MASK) | self.gamepads[1].read(),
+            APU_IO_REGISTERS ..= APU_IO_REGISTERS_END => {
+                self.io.mem_read(addr)
+            }
+            CARTRIDGE_SPACE ..= CARTRIDGE_SPACE_END => {
+                self.cartridge.borrow_mut().cpu_read(addr)
+            }
+        };
+        self.open_bus = data;
+        return data;
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        match addr {
+            RAM ..= RAM_MIRRORS_END => {
+                self.cpu_vram[(addr & RAM_MIRROR_MASK) as usize] = data;
+            }
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.write_register(ppu_register(addr), data);
+            }
+            OAM_DMA => {
+                self.run_oam_dma(data);
+            }
+            APU_IO_REGISTERS ..= APU_CHANNELS_END | apu::STATUS | apu::FRAME_COUNTER => {
+                self.apu.write_register(addr, data);
+            }
+            // Both ports share the strobe line
+            gamepad::JOYPAD1 => {
+                for gamepad in self.gamepads.iter_mut() {
+                    gamepad.write(data);
+                }
+            }
+            APU_IO_REGISTERS ..= APU_IO_REGISTERS_END => {
+                self.io.mem_write(addr, data);
+            }
+            CARTRIDGE_SPACE ..= CARTRIDGE_SPACE_END => {
+                let mut cartridge = self.cartridge.borrow_mut();
+                cartridge.cpu_cycle(self.cycle);
+                cartridge.cpu_write(addr, data);
+            }
+        }
+    }
+}
+
+/// Handler for ranges with nothing attached, reads return 0 and
+/// writes are dropped.
+pub struct OpenBus;
+
+impl Memory for OpenBus {
+    fn mem_peek(&self, _addr: u16) -> u8 {
+        return 0;
+    }
+
+    fn mem_write(&mut self, _addr: u16, _data: u8) {}
+}
@@ -0,0 +1,466 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::cpu::Memory;
+
+// "NES" followed by MS-DOS end of file
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+
+const PRG_ROM_PAGE_SIZE: usize = 16 * 1024;
+const CHR_ROM_PAGE_SIZE: usize = 8 * 1024;
+const PRG_RAM_PAGE_SIZE: usize = 8 * 1024;
+const CHR_RAM_DEFAULT_SIZE: usize = 8 * 1024;
+
+// [0x2000 .. 0x2FFF] 4 logical nametables as seen by the PPU
+const NAMETABLES: u16 = 0x2000;
+const NAMETABLE_SIZE: u16 = 0x400;
+
+// [0x8000 .. 0xFFFF] PRG ROM as seen by the CPU
+const PRG_ROM_START: u16 = 0x8000;
+
+// How the cartridge wires the 4 logical nametables onto VRAM. Set by the
+// header, mappers with mirroring control change it at runtime.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Mirroring {
+    // A B
+    // A B
+    Vertical,
+    // A A
+    // B B
+    Horizontal,
+    // Every nametable is the first (lower) or second (upper) 1 KiB
+    SingleScreenLower,
+    SingleScreenUpper,
+    // 4 distinct nametables, the cartridge supplies the extra 2 KiB
+    FourScreen,
+}
+
+impl Mirroring {
+    // Translates a PPU address in [0x2000 .. 0x2FFF] (or its mirror up to
+    // 0x3EFF) to an offset into nametable VRAM
+    pub fn nametable_addr(&self, addr: u16) -> usize {
+        let addr = addr.wrapping_sub(NAMETABLES) & 0x0FFF;
+        let table = addr / NAMETABLE_SIZE;
+        let offset = addr % NAMETABLE_SIZE;
+
+        let physical = match self {
+            Mirroring::Vertical => table & 1,
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+
+        return (physical * NAMETABLE_SIZE + offset) as usize;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RomFormat {
+    INes,
+    Nes2,
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    // File does not start with "NES\x1A"
+    InvalidMagic,
+    // File is shorter than the sizes declared in its header
+    Truncated { expected: usize, actual: usize },
+    // Value of the version bits (byte 7, bits 2-3) we can't parse
+    UnsupportedVersion(u8),
+    // Sizes declared in the header don't fit in the address space
+    Oversized,
+    // Header declares no PRG ROM, there would be nothing to run
+    NoPrgRom,
+    Io(io::Error),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::InvalidMagic => write!(f, "not an iNES file, bad magic number"),
+            RomError::Truncated { expected, actual } => write!(
+                f, "rom is truncated, expected {} bytes but got {}", expected, actual),
+            RomError::UnsupportedVersion(version) => write!(
+                f, "unsupported iNES header version {:#04b}", version),
+            RomError::Oversized => write!(f, "rom sizes in the header are too large"),
+            RomError::NoPrgRom => write!(f, "rom has no PRG ROM"),
+            RomError::Io(err) => write!(f, "failed to read rom: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+/// Cartridge contents parsed from an iNES 1.0 or NES 2.0 file.
+pub struct Rom {
+    pub format: RomFormat,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    // Size of the CHR RAM the board provides when there is no CHR ROM
+    pub chr_ram_size: usize,
+    pub prg_ram_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+}
+
+impl Rom {
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Rom, RomError> {
+        let raw = fs::read(path)?;
+        return Rom::new(&raw);
+    }
+
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.len() < NES_TAG.len() || raw[0..4] != NES_TAG {
+            return Err(RomError::InvalidMagic);
+        }
+
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::Truncated { expected: HEADER_SIZE, actual: raw.len() });
+        }
+
+        // 0b00 is iNES 1.0 and 0b10 is NES 2.0, the rest are reserved
+        let version = (raw[7] >> 2) & 0b11;
+        let format = match version {
+            0b00 => RomFormat::INes,
+            0b10 => RomFormat::Nes2,
+            _ => return Err(RomError::UnsupportedVersion(version)),
+        };
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let battery = raw[6] & 0b10 != 0;
+        let trainer = raw[6] & 0b100 != 0;
+
+        let mut mapper = ((raw[7] & 0xF0) | (raw[6] >> 4)) as u16;
+        let mut submapper = 0;
+
+        let prg_rom_size;
+        let chr_rom_size;
+        let prg_ram_size;
+        let chr_ram_size;
+
+        match format {
+            RomFormat::INes => {
+                prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+                chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+                // A value of 0 infers 8 KiB for compatibility
+                prg_ram_size = raw[8].max(1) as usize * PRG_RAM_PAGE_SIZE;
+                chr_ram_size = match chr_rom_size {
+                    0 => CHR_RAM_DEFAULT_SIZE,
+                    _ => 0,
+                };
+            }
+            RomFormat::Nes2 => {
+                mapper |= ((raw[8] & 0x0F) as u16) << 8;
+                submapper = raw[8] >> 4;
+                prg_rom_size = nes2_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE)
+                    .ok_or(RomError::Oversized)?;
+                chr_rom_size = nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE)
+                    .ok_or(RomError::Oversized)?;
+                // Volatile and battery backed RAM sizes are both shift counts
+                prg_ram_size = nes2_ram_size(raw[10] & 0x0F) + nes2_ram_size(raw[10] >> 4);
+                chr_ram_size = nes2_ram_size(raw[11] & 0x0F) + nes2_ram_size(raw[11] >> 4);
+            }
+        }
+
+        if prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+
+        let prg_rom_start = HEADER_SIZE + match trainer {
+            true => TRAINER_SIZE,
+            false => 0,
+        };
+        let chr_rom_start = prg_rom_start.checked_add(prg_rom_size).ok_or(RomError::Oversized)?;
+        let expected = chr_rom_start.checked_add(chr_rom_size).ok_or(RomError::Oversized)?;
+
+        if raw.len() < expected {
+            return Err(RomError::Truncated { expected, actual: raw.len() });
+        }
+
+        return Ok(Rom {
+            format,
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..expected].to_vec(),
+            chr_ram_size,
+            prg_ram_size,
+            mapper,
+            submapper,
+            mirroring,
+            battery,
+            trainer,
+        });
+    }
+}
+
+// NES 2.0 stores the ROM size MSB nibble in byte 9. When it is 0xF the LSB
+// byte switches to exponent-multiplier notation: 2^E * (MM * 2 + 1).
+// None when that doesn't fit in a usize.
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> Option<usize> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        return 1usize.checked_shl(exponent)?.checked_mul(multiplier);
+    }
+
+    return Some(((msb as usize) << 8 | lsb as usize) * page_size);
+}
+
+// NES 2.0 RAM sizes are 64 << shift bytes, with 0 meaning no RAM
+fn nes2_ram_size(shift: u8) -> usize {
+    return match shift {
+        0 => 0,
+        _ => 64 << shift,
+    };
+}
+
+impl Memory for Rom {
+    fn mem_peek(&self, addr: u16) -> u8 {
+        if addr < PRG_ROM_START || self.prg_rom.is_empty() {
+            return 0;
+        }
+
+        // 16 KiB carts are mirrored into the upper half of PRG ROM space
+        let offset = (addr - PRG_ROM_START) as usize % self.prg_rom.len();
+        return self.prg_rom[offset];
+    }
+
+    fn mem_write(&mut self, _addr: u16, _data: u8) {
+        // ROM, writes have no effect
+    }
+}
+
+/// iNES images for tests. The header is filled in from the board and the
+/// sizes of the ROMs, NES 2.0 once a submapper is given.
+#[cfg(test)]
+pub struct RomBuilder {
+    mapper: u8,
+    submapper: Option<u8>,
+    battery: bool,
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+}
+
+#[cfg(test)]
+impl RomBuilder {
+
+    pub fn new(mapper: u8) -> Self {
+        RomBuilder {
+            mapper,
+            submapper: None,
+            battery: false,
+            prg_rom: vec![0; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        }
+    }
+
+    pub fn submapper(mut self, submapper: u8) -> Self {
+        self.submapper = Some(submapper);
+        return self;
+    }
+
+    pub fn battery(mut self, battery: bool) -> Self {
+        self.battery = battery;
+        return self;
+    }
+
+    // Whole 16 KiB pages
+    pub fn prg_rom(mut self, prg_rom: Vec<u8>) -> Self {
+        assert_eq!(prg_rom.len() % PRG_ROM_PAGE_SIZE, 0);
+        self.prg_rom = prg_rom;
+        return self;
+    }
+
+    // Whole 8 KiB pages, none for CHR RAM
+    pub fn chr_rom(mut self, chr_rom: Vec<u8>) -> Self {
+        assert_eq!(chr_rom.len() % CHR_ROM_PAGE_SIZE, 0);
+        self.chr_rom = chr_rom;
+        return self;
+    }
+
+    pub fn raw(&self) -> Vec<u8> {
+        let mut flags_6 = self.mapper << 4;
+        if self.battery {
+            flags_6 |= 0b10;
+        }
+        let mut flags_7 = self.mapper & 0xF0;
+        if self.submapper.is_some() {
+            flags_7 |= 0b1000;
+        }
+
+        let mut raw = NES_TAG.to_vec();
+        raw.push((self.prg_rom.len() / PRG_ROM_PAGE_SIZE) as u8);
+        raw.push((self.chr_rom.len() / CHR_ROM_PAGE_SIZE) as u8);
+        raw.push(flags_6);
+        raw.push(flags_7);
+        raw.push(self.submapper.unwrap_or(0) << 4);
+        raw.resize(HEADER_SIZE, 0);
+        raw.extend(&self.prg_rom);
+        raw.extend(&self.chr_rom);
+        return raw;
+    }
+
+    pub fn build(&self) -> Rom {
+        return Rom::new(&self.raw()).unwrap();
+    }
+}
+
+// Banks filled with their number, for tests to tell which one is mapped in
+#[cfg(test)]
+pub fn numbered_banks(count: usize, size: usize) -> Vec<u8> {
+    return (0..count).flat_map(|bank| vec![bank as u8; size]).collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(prg_pages: u8, chr_pages: u8, flags_6: u8, flags_7: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, prg_pages, chr_pages, flags_6, flags_7];
+        raw.resize(HEADER_SIZE, 0);
+        return raw;
+    }
+
+    #[test]
+    fn test_ines_header() {
+        let mut raw = header(2, 1, 0b0001_0011, 0b0100_0000);
+        raw.resize(HEADER_SIZE + 2 * PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE, 0);
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.format, RomFormat::INes);
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_ram_size, 0);
+        assert_eq!(rom.mapper, 0x41);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert!(!rom.trainer);
+    }
+
+    #[test]
+    fn test_trainer_is_skipped() {
+        let mut raw = header(1, 0, 0b0000_0100, 0);
+        raw.resize(HEADER_SIZE + TRAINER_SIZE, 0xFF);
+        raw.resize(HEADER_SIZE + TRAINER_SIZE + PRG_ROM_PAGE_SIZE, 0xEA);
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert!(rom.trainer);
+        assert_eq!(rom.prg_rom[0], 0xEA);
+        assert_eq!(rom.chr_ram_size, CHR_RAM_DEFAULT_SIZE);
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let mut raw = header(1, 0, 0b0000_1000, 0b0000_1000);
+        raw[8] = 0x21;
+        raw[11] = 0x07;
+        raw.resize(HEADER_SIZE + PRG_ROM_PAGE_SIZE, 0);
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.format, RomFormat::Nes2);
+        assert_eq!(rom.mapper, 0x100);
+        assert_eq!(rom.submapper, 2);
+        assert_eq!(rom.mirroring, Mirroring::FourScreen);
+        assert_eq!(rom.chr_ram_size, 8 * 1024);
+    }
+
+    #[test]
+    fn test_bad_roms() {
+        assert!(matches!(Rom::new(&[0x4E, 0x45, 0x53, 0x00]), Err(RomError::InvalidMagic)));
+        assert!(matches!(Rom::new(&header(1, 0, 0, 0b0000_0100)), Err(RomError::UnsupportedVersion(1))));
+        assert!(matches!(
+            Rom::new(&header(1, 1, 0, 0)),
+            Err(RomError::Truncated { expected, actual: HEADER_SIZE })
+                if expected == HEADER_SIZE + PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE));
+        assert!(matches!(Rom::new(&header(0, 1, 0x10, 0)), Err(RomError::NoPrgRom)));
+    }
+
+    #[test]
+    fn test_hostile_nes2_sizes() {
+        // 2^63 * 7 bytes of PRG ROM
+        let mut raw = header(0xFF, 0, 0, 0b0000_1000);
+        raw[9] = 0x0F;
+        assert!(matches!(Rom::new(&raw), Err(RomError::Oversized)));
+
+        // 2^63 bytes each of PRG and CHR ROM, only the total overflows
+        let mut raw = header(63 << 2, 63 << 2, 0, 0b0000_1000);
+        raw[9] = 0xFF;
+        assert!(matches!(Rom::new(&raw), Err(RomError::Oversized)));
+    }
+
+    #[test]
+    fn test_nametable_vertical() {
+        let mirroring = Mirroring::Vertical;
+        assert_eq!(mirroring.nametable_addr(0x2005), 0x005);
+        assert_eq!(mirroring.nametable_addr(0x2405), 0x405);
+        assert_eq!(mirroring.nametable_addr(0x2805), 0x005);
+        assert_eq!(mirroring.nametable_addr(0x2C05), 0x405);
+    }
+
+    #[test]
+    fn test_nametable_horizontal() {
+        let mirroring = Mirroring::Horizontal;
+        assert_eq!(mirroring.nametable_addr(0x2005), 0x005);
+        assert_eq!(mirroring.nametable_addr(0x2405), 0x005);
+        assert_eq!(mirroring.nametable_addr(0x2805), 0x405);
+        assert_eq!(mirroring.nametable_addr(0x2C05), 0x405);
+    }
+
+    #[test]
+    fn test_nametable_single_screen() {
+        for addr in [0x2005, 0x2405, 0x2805, 0x2C05] {
+            assert_eq!(Mirroring::SingleScreenLower.nametable_addr(addr), 0x005);
+            assert_eq!(Mirroring::SingleScreenUpper.nametable_addr(addr), 0x405);
+        }
+    }
+
+    #[test]
+    fn test_nametable_four_screen() {
+        let mirroring = Mirroring::FourScreen;
+        assert_eq!(mirroring.nametable_addr(0x2005), 0x005);
+        assert_eq!(mirroring.nametable_addr(0x2405), 0x405);
+        assert_eq!(mirroring.nametable_addr(0x2805), 0x805);
+        assert_eq!(mirroring.nametable_addr(0x2C05), 0xC05);
+    }
+
+    #[test]
+    fn test_nametable_mirrors_above_0x3000() {
+        assert_eq!(Mirroring::FourScreen.nametable_addr(0x3C05), 0xC05);
+        assert_eq!(Mirroring::Vertical.nametable_addr(0x3EFF), 0x6FF);
+    }
+
+    #[test]
+    fn test_prg_rom_mirroring() {
+        let mut raw = header(1, 0, 0, 0);
+        raw.resize(HEADER_SIZE + PRG_ROM_PAGE_SIZE, 0);
+        raw[HEADER_SIZE + 0x3FFC] = 0x00;
+        raw[HEADER_SIZE + 0x3FFD] = 0x80;
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.mem_peek_u16(0xBFFC), 0x8000);
+        assert_eq!(rom.mem_peek_u16(0xFFFC), 0x8000);
+    }
+}
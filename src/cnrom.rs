@@ -0,0 +1,97 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bus_conflict, chr_memory, has_bus_conflicts, Mapper, PRG_ROM};
+
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+/// Mapper 3, NROM PRG with an 8 KiB CHR ROM bank selected by any write
+/// to PRG space.
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    pub bank: u8,
+}
+
+impl Cnrom {
+
+    pub fn new(rom: Rom) -> Self {
+        let bus_conflicts = has_bus_conflicts(&rom, true);
+        let (chr, chr_ram) = chr_memory(rom.chr_rom);
+
+        Cnrom {
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_ram,
+            mirroring: rom.mirroring,
+            bus_conflicts,
+            bank: 0,
+        }
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        return (self.bank as usize * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE) % self.chr.len();
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        if addr < PRG_ROM || self.prg_rom.is_empty() {
+            return 0;
+        }
+
+        return self.prg_rom[(addr - PRG_ROM) as usize % self.prg_rom.len()];
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= PRG_ROM {
+            self.bank = bus_conflict(data, self.cpu_peek(addr), self.bus_conflicts);
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        return self.chr[self.chr_addr(addr)];
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let index = self.chr_addr(addr);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{numbered_banks, RomBuilder};
+
+    #[test]
+    fn test_cnrom() {
+        // Only byte 0 conflicts with the bank numbers written
+        let mut prg_rom = vec![0xFF; 16 * 1024];
+        prg_rom[0] = 0;
+        let mut cnrom = Cnrom::new(RomBuilder::new(3)
+            .prg_rom(prg_rom)
+            .chr_rom(numbered_banks(4, CHR_BANK_SIZE))
+            .build());
+        assert_eq!(cnrom.ppu_read(0x0000), 0);
+
+        cnrom.cpu_write(0x8001, 2);
+        assert_eq!(cnrom.ppu_read(0x0000), 2);
+        assert_eq!(cnrom.ppu_read(0x1FFF), 2);
+
+        // CHR ROM can't be written, PRG is NROM's
+        cnrom.ppu_write(0x0000, 0x55);
+        assert_eq!(cnrom.ppu_read(0x0000), 2);
+        assert_eq!(cnrom.cpu_peek(0xC000), 0);
+
+        cnrom.cpu_write(0x8000, 3);
+        assert_eq!(cnrom.ppu_read(0x0000), 0);
+    }
+}
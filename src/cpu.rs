@@ -1,7 +1,10 @@
-use std::{ops::{BitAnd, BitOr, BitOrAssign}, u8};
+use std::{fmt, io, ops::{BitAnd, BitOr, BitOrAssign}, u8};
 
 use int_enum::IntEnum;
 use crate::opcodes::{*};
+use crate::bus::Bus;
+use crate::cartridge::Rom;
+use crate::mapper::{self, MapperError};
 use bitflags::bitflags;
 
 pub struct CPU {
@@ -13,48 +16,98 @@ pub struct CPU {
     pub counter_state: u16,
     // The length of the stack STACK_START + stack_pointer to get end of stack
     pub stack_pointer: u8,
-    // [0x80000 .. 0xFFFF] Program ROM
-    memory: [u8; PGRM_ROM_END as usize]
+    // Total number of cycles executed since power on
+    pub cycles: u64,
+    // Test harness mode, BRK stops the run loop instead of interrupting
+    pub halt_on_brk: bool,
+    // Turn off for a strict 6502 where undocumented opcodes trap
+    pub illegal_opcodes: bool,
+    // NMI is edge triggered, latched until serviced
+    nmi_pending: bool,
+    // IRQ is level triggered, serviced while asserted and not disabled
+    irq_line: bool,
+    // First error raised while executing the current instruction
+    fault: Option<CpuError>,
+    // Set by branch when the current instruction took its branch
+    branch_taken: bool,
+    pub bus: Bus
 }
 
-// Beginning and end of the available Program ROM memory
+/// What a single call to CPU::step executed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Step {
+    // Where the instruction was fetched from
+    pub addr: u16,
+    pub opcode: u8,
+    pub mode: AddressingMode,
+    // Resolved operand address, None for modes without one
+    pub operand_addr: Option<u16>,
+    // Cycles spent on the instruction, including page crossing and
+    // branch penalties but not servicing an interrupt before it
+    pub cycles: u64,
+    pub branch_taken: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CpuError {
+    // Byte code with no opcode, or an undocumented one while they are off
+    IllegalOpcode { opcode: u8, addr: u16 },
+    // Instruction asked for an operand with a mode it can't resolve
+    InvalidAddressingMode { mode: AddressingMode, addr: u16 },
+    // A JAM opcode locked up the CPU, only a reset gets it going again
+    Jammed { opcode: u8, addr: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { opcode, addr } => write!(
+                f, "illegal opcode ${:02X} at ${:04X}", opcode, addr),
+            CpuError::InvalidAddressingMode { mode, addr } => write!(
+                f, "addressing mode {:?} has no operand, instruction at ${:04X}", mode, addr),
+            CpuError::Jammed { opcode, addr } => write!(
+                f, "cpu jammed by ${:02X} at ${:04X}", opcode, addr),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+// Beginning of the available Program ROM memory
 const PGRM_ROM_START: u16 = 0x8000;
-const PGRM_ROM_END: u16 = 0xFFFF;
 // Address stored within cartridge which indicates where execution begins
 const PGRM_START_ADDR: u16 = 0xFFFC;
+// Interrupt vectors, BRK shares the IRQ vector
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_BRK_VECTOR: u16 = 0xFFFE;
+
+// CPU is halted for 513 cycles during OAM DMA, 514 when it starts on an
+// odd cycle
+const OAM_DMA_CYCLES: u64 = 513;
 
 const STACK: u16 = 0x0100;
 const STACK_RESET: u8 = 0xFD;
 
-#[macro_export]
-macro_rules! execute {
-    ($cpu:tt, $byte_code:tt, {$($opcode:ident::$mode:ident),*,}) => {
-        match $byte_code {
-            // Need to check specficially for BRK code
-            // since it stops execution.
-            0x00 => {
-                // unsafe {
-                //     use std::fs::File;
-                //     use std::io::prelude::*;
-                //     let mut file = File::create("history.txt").unwrap();
-                //     file.write(crate::opcodes::HISTORY.as_bytes()).unwrap();
-                // }                
-                return;
+// Generates the dispatch function matching a byte code to its opcode
+// module along with the table of every byte code it handles
+macro_rules! dispatch_table {
+    ($table:ident, $dispatch:ident, {$($($opcode:ident)::+),*,}) => {
+        // Every opcode value dispatched, for the tests to check coverage
+        #[cfg(test)]
+        pub const $table: &[u8] = &[$($($opcode)::+::VALUE),*];
+
+        // Executes the opcode for byte_code, false if there is none
+        pub fn $dispatch(cpu: &mut CPU, byte_code: u8) -> bool {
+            match byte_code {
+                $(
+                    $($opcode)::+::VALUE => {
+                        $($opcode)::+::execute(cpu);
+                    }
+                )*
+                _ => return false
             }
 
-            $(
-                $opcode::$mode::VALUE => {
-                    $opcode::$mode::execute($cpu);
-                }
-            )*
-            _ => { 
-                use std::fs::File;
-                use std::io::prelude::*;
-                let mut file = File::create("memory.txt").unwrap();
-                let mem_dump = format!("{:#04X?}", &$cpu.memory);
-                file.write(mem_dump.as_bytes()).unwrap();
-                panic!("No opcode for {:#04X?}", $byte_code) 
-            }
+            return true;
         }
     }
 }
@@ -83,16 +136,30 @@ const SNAKE_GAME: [u8; 16 * 19 + 5] = [
 ];
 
 pub trait Memory {
-    fn mem_read(&self, addr: u16) -> u8;
+    // Reads without side effects, for the tracer, disassembler and
+    // memory dumps. Registers which change on read report their value
+    // as if they had been read.
+    fn mem_peek(&self, addr: u16) -> u8;
+
+    // Reads as the CPU does, e.g. reading PPUSTATUS clears VBlank
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        return self.mem_peek(addr);
+    }
 
     fn mem_write(&mut self, addr: u16, data: u8);
 
-    fn mem_read_u16(&self, pos: u16) -> u16 {
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
         return u16::from_le_bytes(
             [self.mem_read(pos), self.mem_read(pos + 1)]
         );
     }
 
+    fn mem_peek_u16(&self, pos: u16) -> u16 {
+        return u16::from_le_bytes(
+            [self.mem_peek(pos), self.mem_peek(pos + 1)]
+        );
+    }
+
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
         let bytes = data.to_le_bytes();
         self.mem_write(pos, bytes[0]);
@@ -101,36 +168,59 @@ pub trait Memory {
 }
 
 impl Memory for CPU {
-    fn mem_read(&self, addr: u16) -> u8 {
-        return self.memory[addr as usize];
+    fn mem_peek(&self, addr: u16) -> u8 {
+        return self.bus.mem_peek(addr);
+    }
+
+    // Once the instruction has faulted nothing more reaches the bus
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        if self.fault.is_some() {
+            return 0;
+        }
+        return self.bus.mem_read(addr);
     }
 
+    // The cycles are counted up front, writes land on the last one
     fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+        self.write_on_cycle(addr, data, self.cycles.saturating_sub(1));
     }
 }
 
 impl CPU {
 
+    fn write_on_cycle(&mut self, addr: u16, data: u8, cycle: u64) {
+        if self.fault.is_some() {
+            return;
+        }
+        self.bus.cycle = cycle;
+        self.bus.mem_write(addr, data);
+    }
+
+    // Read-modify-write instructions write the unmodified value back the
+    // cycle before the result, mappers like the MMC1 see both
+    pub fn write_modified(&mut self, addr: u16, old: u8, new: u8) {
+        self.write_on_cycle(addr, old, self.cycles.saturating_sub(2));
+        self.write_on_cycle(addr, new, self.cycles.saturating_sub(1));
+    }
+
     pub fn load_snake(&mut self) {
-        self.memory[0x0600..(0x0600 + SNAKE_GAME.len())].copy_from_slice(&SNAKE_GAME[..]);
+        for (i, byte) in SNAKE_GAME.iter().enumerate() {
+            self.mem_write(0x0600 + i as u16, *byte);
+        }
         self.mem_write_u16(0xFFFC, 0x0600)
     }
 
-    pub fn run_snake_with_callback<F>(&mut self, mut call_back: F)
+    pub fn run_snake_with_callback<F>(&mut self, mut call_back: F) -> Result<(), CpuError>
     where F: FnMut(&mut CPU),
     {
-        use crate::exec_opcodes;
-        //let mut execution: Vec<u8> = vec![];
-        
         loop {
-            let byte_code = self.mem_read(self.counter);
-            self.counter += 1;
-            self.counter_state = self.counter;
+            self.service_interrupts();
 
-            exec_opcodes!(self, byte_code);
+            if self.halting_brk() {
+                return Ok(());
+            }
 
-            //execution.push(byte_code);            
+            self.step()?;
 
             call_back(self);
         }
@@ -148,7 +238,14 @@ impl CPU {
             counter: 0,
             counter_state: 0,
             stack_pointer: STACK_RESET,
-            memory: [0; PGRM_ROM_END as usize]
+            cycles: 0,
+            halt_on_brk: false,
+            illegal_opcodes: true,
+            nmi_pending: false,
+            irq_line: false,
+            fault: None,
+            branch_taken: false,
+            bus: Bus::new()
         }
     }
 
@@ -197,7 +294,7 @@ impl CPU {
 
         let result = sum as u8;
 
-        match (data ^ result) & (result ^ self.register_a) & 0x90 {
+        match (data ^ result) & (result ^ self.register_a) & 0x80 {
             0 => {self.status.remove(Flag::Overflow);}
             _ => {self.status.insert(Flag::Overflow);}
         }
@@ -219,10 +316,62 @@ impl CPU {
         self.stack_pointer = STACK_RESET;
     }
 
-    pub fn compare(&mut self, mode: AddressingMode, mut value: u8) {
-        let addr = self.get_operand_addr(mode);
-        let data = self.mem_read(addr);
+    // Raises a non maskable interrupt, serviced before the next instruction
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
 
+    // Holds the IRQ line low, serviced before every instruction until
+    // released as long as InterruptDisable is clear. The APU and mappers
+    // raise theirs through the bus, this stands in for them in tests.
+    #[cfg(test)]
+    pub fn assert_irq(&mut self) {
+        self.irq_line = true;
+    }
+
+    #[cfg(test)]
+    pub fn release_irq(&mut self) {
+        self.irq_line = false;
+    }
+
+    pub fn poll_interrupts(&mut self) {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(Interrupt::NMI);
+        } else if (self.irq_line || self.bus.irq()) && !self.status.contains(Flag::InterruptDisable) {
+            self.interrupt(Interrupt::IRQ);
+        }
+    }
+
+    // Pushes counter and status then jumps through the interrupt's vector.
+    // https://www.nesdev.org/wiki/CPU_interrupts
+    pub fn interrupt(&mut self, interrupt: Interrupt) {
+        self.stack_push_u16(self.counter);
+
+        // Bit 5 is always pushed as 1, bit 4 (B) only when pushed by BRK
+        let mut flags = self.status;
+        flags.insert(Flag::BreakCommand2);
+        flags.set(Flag::BreakCommand, interrupt == Interrupt::BRK);
+        self.stack_push(flags.bits());
+
+        self.status.insert(Flag::InterruptDisable);
+
+        // BRK adds its 7 cycles through its opcode module
+        if interrupt != Interrupt::BRK {
+            self.cycles += 7;
+        }
+
+        self.counter = self.mem_read_u16(interrupt.vector());
+    }
+
+    pub fn compare(&mut self, mode: AddressingMode, value: u8) {
+        let data = self.read_operand(mode);
+
+        self.compare_data(data, value);
+    }
+
+    // Flags for value - data, shared by compares which already read data
+    pub fn compare_data(&mut self, data: u8, mut value: u8) {
         if data <= value {
             self.status.insert(Flag::Carry);
         } else {
@@ -235,6 +384,42 @@ impl CPU {
         self.update_flag(Flag::Negative,value);
     }
     
+    pub fn arithmetic_shift_left_a(&mut self) {
+        let mut data = self.register_a;
+
+        // If a bit is left over set Carry flag
+        match data >> 7 {
+            1 => self.status.insert(Flag::Carry),
+            _ => self.status.remove(Flag::Carry)
+        }
+
+        data <<= 1;
+        self.register_a = data;
+
+        self.update_flag(Flag::Zero, data);
+        self.update_flag(Flag::Negative, data);
+    }
+
+    pub fn arithmetic_shift_left(&mut self, mode: AddressingMode) -> u8 {
+        let addr = self.get_operand_addr(mode);
+        let old = self.mem_read(addr);
+        let mut data = old;
+
+        // If a bit is left over set Carry flag
+        match data >> 7 {
+            1 => self.status.insert(Flag::Carry),
+            _ => self.status.remove(Flag::Carry)
+        }
+
+        data <<= 1;
+        self.write_modified(addr, old, data);
+
+        self.update_flag(Flag::Zero, data);
+        self.update_flag(Flag::Negative, data);
+
+        return data;
+    }
+
     pub fn logical_shift_right_a(&mut self) {
 
         let mut data = self.register_a;
@@ -256,7 +441,8 @@ impl CPU {
     pub fn logical_shift_right(&mut self, mode: AddressingMode) -> u8 {
 
         let addr = self.get_operand_addr(mode);
-        let mut data = self.mem_read(addr);
+        let old = self.mem_read(addr);
+        let mut data = old;
 
         match data & 1 {
             1 => self.status.insert(Flag::Carry),
@@ -264,7 +450,7 @@ impl CPU {
         }
 
         data = data >> 1;
-        self.mem_write(addr, data);
+        self.write_modified(addr, old, data);
 
         self.update_flag(Flag::Zero, data);
         self.update_flag(Flag::Negative, data);
@@ -294,7 +480,8 @@ impl CPU {
 
     pub fn rotate_left(&mut self, mode: AddressingMode) -> u8 {
         let addr = self.get_operand_addr(mode);
-        let mut data = self.mem_read(addr);
+        let old = self.mem_read(addr);
+        let mut data = old;
         let had_carry = self.status.contains(Flag::Carry);
 
         match data >> 7 {
@@ -308,7 +495,8 @@ impl CPU {
             data |= 1;
         }
 
-        self.mem_write(addr, data);
+        self.write_modified(addr, old, data);
+        self.update_flag(Flag::Zero, data);
         self.update_flag(Flag::Negative, data);
 
         return data;
@@ -318,7 +506,7 @@ impl CPU {
         let mut data = self.register_a;
         let had_carry = self.status.contains(Flag::Carry);
 
-        match data << 7 {
+        match data & 1 {
             1 => self.status.insert(Flag::Carry),
             _ => self.status.remove(Flag::Carry)
         }
@@ -336,10 +524,11 @@ impl CPU {
 
     pub fn rotate_right(&mut self, mode: AddressingMode) -> u8 {
         let addr = self.get_operand_addr(mode);
-        let mut data = self.mem_read(addr);
+        let old = self.mem_read(addr);
+        let mut data = old;
         let had_carry = self.status.contains(Flag::Carry);
 
-        match data << 7 {
+        match data & 1 {
             1 => self.status.insert(Flag::Carry),
             _ => self.status.remove(Flag::Carry)
         }
@@ -350,7 +539,7 @@ impl CPU {
             data = data | 0b1000_0000;
         }
 
-        self.mem_write(addr, data);
+        self.write_modified(addr, old, data);
         self.update_flag(Flag::Zero, data);
         self.update_flag(Flag::Negative, data);
 
@@ -358,8 +547,7 @@ impl CPU {
     }
 
     pub fn load_into(&mut self, mode: AddressingMode, register: Register) {
-        let addr = self.get_operand_addr(mode);
-        let val = self.mem_read(addr);
+        let val = self.read_operand(mode);
 
         match register {
             Register::A => {
@@ -382,11 +570,12 @@ impl CPU {
 
     pub fn decrement_memory(&mut self, mode: AddressingMode) -> u8 {
         let addr: u16 = self.get_operand_addr(mode);
-        let mut data: u8 = self.mem_read(addr);
+        let old: u8 = self.mem_read(addr);
+        let mut data = old;
 
         data = data.wrapping_sub(1);
 
-        self.mem_write(addr, data);
+        self.write_modified(addr, old, data);
 
         self.update_flag(Flag::Zero, data);
         self.update_flag(Flag::Negative, data);
@@ -408,13 +597,14 @@ impl CPU {
 
     pub fn increment_memory(&mut self, mode: AddressingMode) -> u8 {
         let addr = self.get_operand_addr(mode);
-        let mut data = self.mem_read(addr);
+        let old = self.mem_read(addr);
+        let mut data = old;
 
         data = data.wrapping_add(1);
 
-        self.mem_write(addr, data);
-        self.update_flag(Flag::Zero, data);
+        self.write_modified(addr, old, data);
         self.update_flag(Flag::Zero, data);
+        self.update_flag(Flag::Negative, data);
         
         return data;
     }
@@ -422,12 +612,17 @@ impl CPU {
     pub fn branch(&mut self, case: bool) {
         if case {
             let jump: i8 = self.mem_read(self.counter) as i8;
-            let addr = self
-                .counter
-                .wrapping_add(1)
-                .wrapping_add(jump as u16);
+            let next = self.counter.wrapping_add(1);
+            let addr = next.wrapping_add(jump as u16);
+
+            // +1 cycle if the branch is taken, +2 if it lands on a new page
+            self.cycles += 1;
+            if next & 0xFF00 != addr & 0xFF00 {
+                self.cycles += 1;
+            }
 
             self.counter = addr;
+            self.branch_taken = true;
         }
     }
 
@@ -462,98 +657,315 @@ impl CPU {
 
                 self.counter = indirect_ref;
             }
-            _ => panic!("JUMP PROVIDED INCORRECT ADDRESSING MODE")
+            _ => {
+                let addr = self.counter_state.wrapping_sub(1);
+                self.raise(CpuError::InvalidAddressingMode { mode, addr });
+            }
         }
     }
 
     pub fn load(&mut self, program: Vec<u8>) {
         // load method should load a program into PRG ROM space and save the reference to the code into 0xFFFC memory cell
-        self.memory[PGRM_ROM_START as usize .. (PGRM_ROM_START as usize + program.len())].copy_from_slice(&program[..]);
+        for (i, byte) in program.iter().enumerate() {
+            self.mem_write(PGRM_ROM_START + i as u16, *byte);
+        }
         // Needs to be set to addr stored in start addr const
         self.mem_write_u16(PGRM_START_ADDR, PGRM_ROM_START);
     }
 
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
+    // Plugs the cartridge into the bus, its PRG ROM supplies the reset vector
+    // and its CHR the PPU pattern tables
+    pub fn load_rom(&mut self, rom: Rom) -> Result<(), MapperError> {
+        let cartridge = mapper::from_rom(rom)?;
+        self.bus.insert_cartridge(cartridge);
+        return Ok(());
+    }
+
+    pub fn load_and_run(&mut self, program: Vec<u8>) -> Result<(), CpuError> {
         self.load(program);
         self.reset_interrupt();
-        self.run();
+        return self.run();
     }
 
-    pub fn get_operand_addr(&self, mode: AddressingMode) -> u16 {
+    // Reads the operand of instructions which take an extra cycle when
+    // indexing crosses a page boundary (ABSOLUTE_X, ABSOLUTE_Y, INDIRECT_Y)
+    pub fn read_operand(&mut self, mode: AddressingMode) -> u8 {
+        let addr = self.get_operand_addr(mode);
+
+        if self.page_crossed(mode) {
+            self.cycles += 1;
+        }
+
+        return self.mem_read(addr);
+    }
+
+    pub fn page_crossed(&self, mode: AddressingMode) -> bool {
         use AddressingMode::*;
-        match mode {
-            IMMEDIATE => self.counter,
+        let base = match mode {
+            ABSOLUTE_X | ABSOLUTE_Y => self.mem_peek_u16(self.counter),
+            INDIRECT_Y => {
+                let ptr = self.mem_peek(self.counter);
+                let lo = self.mem_peek(ptr as u16);
+                let hi = self.mem_peek(ptr.wrapping_add(1) as u16);
+                (hi as u16) << 8 | (lo as u16)
+            }
+            _ => return false,
+        };
+
+        let addr = self.get_absolute_addr(mode, self.counter).unwrap_or(base);
+        return base & 0xFF00 != addr & 0xFF00;
+    }
+
+    // Address of the current instruction's operand. A mode without one
+    // faults the instruction, step returns the error once it finishes.
+    pub fn get_operand_addr(&mut self, mode: AddressingMode) -> u16 {
+        return match self.get_absolute_addr(mode, self.counter) {
+            Ok(addr) => {
+                self.bus.open_bus = self.last_address_byte(mode, addr);
+                addr
+            }
+            Err(err) => {
+                self.raise(err);
+                0
+            }
+        };
+    }
 
-            ZERO_PAGE => self.mem_read(self.counter) as u16,
+    // The address bytes are peeked, this is the last of them the data bus
+    // would have carried before the operand itself is accessed
+    fn last_address_byte(&self, mode: AddressingMode, addr: u16) -> u8 {
+        use AddressingMode::*;
+        return match mode {
+            ZERO_PAGE | ZERO_PAGE_X | ZERO_PAGE_Y => self.mem_peek(self.counter),
+            ABSOLUTE | ABSOLUTE_X | ABSOLUTE_Y => self.mem_peek(self.counter.wrapping_add(1)),
+            // High byte of the pointer, before indexing by Y
+            INDIRECT_X => (addr >> 8) as u8,
+            INDIRECT_Y => (addr.wrapping_sub(self.register_y as u16) >> 8) as u8,
+            IMMEDIATE | NONE_ADDRESSING => self.bus.open_bus,
+        };
+    }
+
+    // Resolves the address of an operand stored at pos, this lets the
+    // tracer look at instructions before counter has moved past the opcode
+    pub fn get_absolute_addr(&self, mode: AddressingMode, pos: u16) -> Result<u16, CpuError> {
+        use AddressingMode::*;
+        let addr = match mode {
+            IMMEDIATE => pos,
+
+            ZERO_PAGE => self.mem_peek(pos) as u16,
 
-            ABSOLUTE => self.mem_read_u16(self.counter),
+            ABSOLUTE => self.mem_peek_u16(pos),
 
             ZERO_PAGE_X => {
-                let pos = self.mem_read(self.counter);
+                let pos = self.mem_peek(pos);
                 let addr = pos.wrapping_add(self.register_x) as u16;
                 
-                return addr;
+                addr
             },
 
             ZERO_PAGE_Y => {
-                let pos = self.mem_read(self.counter);
+                let pos = self.mem_peek(pos);
                 let addr = pos.wrapping_add(self.register_y) as u16;
 
-                return addr;
+                addr
             },
 
             ABSOLUTE_X => {
-                let base = self.mem_read_u16(self.counter);
+                let base = self.mem_peek_u16(pos);
                 let addr = base.wrapping_add(self.register_x as u16);
 
-                return addr;
+                addr
             }
 
             ABSOLUTE_Y => {
-                let base = self.mem_read_u16(self.counter);
+                let base = self.mem_peek_u16(pos);
                 let addr = base.wrapping_add(self.register_y as u16);
 
-                return addr;
+                addr
             }
 
             INDIRECT_X => {
-                let base = self.mem_read(self.counter);
+                let base = self.mem_peek(pos);
 
                 let ptr: u8 = (base as u8).wrapping_add(self.register_x);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+                let lo = self.mem_peek(ptr as u16);
+                let hi = self.mem_peek(ptr.wrapping_add(1) as u16);
 
-                return (hi as u16) << 8 | (lo as u16);
+                (hi as u16) << 8 | (lo as u16)
             }
 
             INDIRECT_Y => {
-                let base = self.mem_read(self.counter);
+                let base = self.mem_peek(pos);
 
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
+                let lo = self.mem_peek(base as u16);
+                let hi = self.mem_peek((base as u8).wrapping_add(1) as u16);
                 let deref_base = (hi as u16) << 8 | (lo as u16);
                 let deref = deref_base.wrapping_add(self.register_y as u16);
 
-                return deref;
+                deref
             }
             
             NONE_ADDRESSING => {
-                panic!("mode {:?} is not supported", mode);
+                // pos is the operand, the opcode sits right before it
+                return Err(CpuError::InvalidAddressingMode { mode, addr: pos.wrapping_sub(1) });
             }
-        }
+        };
+
+        return Ok(addr);
     }
 
-    pub fn run(&mut self) {
-        use crate::exec_opcodes;
+    pub fn run(&mut self) -> Result<(), CpuError> {
+        return self.run_with_callback(|_| {});
+    }
 
+    // Calls call_back before each instruction executes, after any pending
+    // interrupt has been serviced, e.g. to trace the instruction about to run
+    pub fn run_with_callback<F>(&mut self, mut call_back: F) -> Result<(), CpuError>
+    where F: FnMut(&mut CPU),
+    {
         loop {
-            let byte_code = self.mem_read(self.counter);
-            self.counter += 1;
+            self.service_interrupts();
 
-            exec_opcodes!(self, byte_code);
+            call_back(self);
+
+            if self.halting_brk() {
+                return Ok(());
+            }
+
+            self.step()?;
         }
     }
 
+    // Services any pending interrupt then executes a single instruction.
+    // An instruction which fails is undone, registers and cycles are put
+    // back and counter is left on it. Nothing it does past the fault
+    // reaches the bus.
+    pub fn step(&mut self) -> Result<Step, CpuError> {
+        return self.step_with(|cpu, byte_code| {
+            return dispatch(cpu, byte_code)
+                || (cpu.illegal_opcodes && dispatch_illegal(cpu, byte_code));
+        });
+    }
+
+    // Step with execute standing in for the dispatch tables, which return
+    // false for a byte code they have no opcode for
+    pub fn step_with<F>(&mut self, execute: F) -> Result<Step, CpuError>
+    where F: FnOnce(&mut CPU, u8) -> bool,
+    {
+        // Raised by calling an instruction outside of a step, not this one
+        self.fault = None;
+
+        let start = self.cycles;
+        self.poll_interrupts();
+
+        let addr = self.counter;
+        let byte_code = self.mem_read(addr);
+        let cycles = self.cycles;
+        let registers = (self.register_a, self.register_x, self.register_y, self.status, self.stack_pointer);
+
+        // Resolved before executing, the instruction may change the
+        // registers the address is indexed by
+        let mode = match lookup(byte_code) {
+            Some(opcode) => opcode.mode,
+            None => AddressingMode::NONE_ADDRESSING,
+        };
+        let operand_addr = match mode {
+            AddressingMode::NONE_ADDRESSING => None,
+            _ => self.get_absolute_addr(mode, addr.wrapping_add(1)).ok(),
+        };
+
+        self.counter = self.counter.wrapping_add(1);
+        self.counter_state = self.counter;
+        self.branch_taken = false;
+
+        let fault = match execute(self, byte_code) {
+            true => self.take_fault(),
+            false => Some(CpuError::IllegalOpcode { opcode: byte_code, addr }),
+        };
+        if let Some(err) = fault {
+            (self.register_a, self.register_x, self.register_y, self.status, self.stack_pointer) = registers;
+            self.counter = addr;
+            self.cycles = cycles;
+            // An interrupt serviced before it still took its cycles
+            self.catch_up(start);
+            return Err(err);
+        }
+
+        if self.bus.take_oam_dma() {
+            self.cycles += OAM_DMA_CYCLES + self.cycles % 2;
+        }
+        self.catch_up(start);
+
+        return Ok(Step {
+            addr,
+            opcode: byte_code,
+            mode,
+            operand_addr,
+            cycles: self.cycles - cycles,
+            branch_taken: self.branch_taken,
+        });
+    }
+
+    // Polls interrupts outside of step, the bus is caught up with the
+    // cycles spent servicing one
+    fn service_interrupts(&mut self) {
+        let start = self.cycles;
+        self.poll_interrupts();
+        self.catch_up(start);
+    }
+
+    // Catches the PPU and APU up with the cycles run since start, VBlank
+    // NMI is serviced before the next step
+    fn catch_up(&mut self, start: u64) {
+        self.bus.tick(self.cycles - start);
+        self.cycles += self.bus.take_dmc_stall();
+        if self.bus.ppu.take_nmi() {
+            self.trigger_nmi();
+        }
+    }
+
+    // When running under a test harness BRK stops the run loops instead
+    // of jumping through the IRQ / BRK vector, counter ends up past it.
+    fn halting_brk(&mut self) -> bool {
+        if !self.halt_on_brk || self.mem_read(self.counter) != BRK::NONE_ADDRESSING::VALUE {
+            return false;
+        }
+
+        self.counter = self.counter.wrapping_add(1);
+        return true;
+    }
+
+    // Records an error for the instruction being executed, only the
+    // first one is kept as the rest tend to follow from it
+    pub fn raise(&mut self, err: CpuError) {
+        if self.fault.is_none() {
+            self.fault = Some(err);
+        }
+    }
+
+    // Hands over the error raised by the instruction, bus access resumes
+    pub fn take_fault(&mut self) -> Option<CpuError> {
+        return self.fault.take();
+    }
+
+    // Executed by the JAM opcodes, counter is put back on the opcode so
+    // every later step jams again
+    pub fn jam(&mut self) {
+        self.counter = self.counter.wrapping_sub(1);
+        let opcode = self.mem_read(self.counter);
+        self.raise(CpuError::Jammed { opcode, addr: self.counter });
+    }
+
+    // Every byte of the address space as the CPU currently sees it
+    pub fn dump_memory(&self) -> Vec<u8> {
+        return (0..=u16::MAX).map(|addr| self.mem_peek(addr)).collect();
+    }
+
+    // Writes dump_memory in the same format the crash dump used to
+    pub fn write_memory_dump<W: io::Write>(&self, mut out: W) -> io::Result<()> {
+        return out.write_all(format!("{:#04X?}", self.dump_memory()).as_bytes());
+    }
+
     pub fn update_flag(&mut self, flag: Flag, register: u8) {
         
         match flag {
@@ -593,240 +1005,356 @@ bitflags! {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Interrupt {
+    NMI,
+    IRQ,
+    BRK
+}
+
+impl Interrupt {
+    pub fn vector(&self) -> u16 {
+        return match self {
+            Interrupt::NMI => NMI_VECTOR,
+            Interrupt::IRQ | Interrupt::BRK => IRQ_BRK_VECTOR,
+        }
+    }
+}
+
 pub enum Register {
     A,
     X,
     Y       
 }
 
-// Temporarily moved this setup to a macro in order
-// to throw this into the snake impl for CPU without
-// having to paste the entire thing in...
-#[macro_export]
-#[allow(unused)]
-macro_rules! exec_opcodes {
-    ($cpu:tt, $byte_code:tt) => {
-        execute!(
-            $cpu, 
-            $byte_code, 
-            {
-                /* Special */
-                BRK::NONE_ADDRESSING,
-
-                NOP::NONE_ADDRESSING,
-
-                /* Arithmetic */
-                ADC::IMMEDIATE, 
-                ADC::ZERO_PAGE, 
-                ADC::ZERO_PAGE_X, 
-                ADC::ABSOLUTE, 
-                ADC::ABSOLUTE_X, 
-                ADC::ABSOLUTE_Y,
-
-                SBC::IMMEDIATE,
-                SBC::ZERO_PAGE,
-                SBC::ZERO_PAGE_X,
-                SBC::ABSOLUTE,
-                SBC::ABSOLUTE_X,
-                SBC::ABSOLUTE_Y,
-                SBC::INDIRECT_X,
-                SBC::INDIRECT_Y,
-                
-                AND::IMMEDIATE, 
-                AND::ZERO_PAGE, 
-                AND::ZERO_PAGE_X, 
-                AND::ABSOLUTE, 
-                AND::ABSOLUTE_X, 
-                AND::ABSOLUTE_Y,
-                AND::INDIRECT_X, 
-                AND::INDIRECT_Y,
-
-                EOR::IMMEDIATE, 
-                EOR::ZERO_PAGE, 
-                EOR::ZERO_PAGE_X, 
-                EOR::ABSOLUTE, 
-                EOR::ABSOLUTE_X, 
-                EOR::ABSOLUTE_Y,
-                EOR::INDIRECT_X, 
-                EOR::INDIRECT_Y,
-
-                ORA::IMMEDIATE, 
-                ORA::ZERO_PAGE, 
-                ORA::ZERO_PAGE_X, 
-                ORA::ABSOLUTE, 
-                ORA::ABSOLUTE_X, 
-                ORA::ABSOLUTE_Y,
-                ORA::INDIRECT_X, 
-                ORA::INDIRECT_Y,
-
-                DEC::ZERO_PAGE, 
-                DEC::ZERO_PAGE_X, 
-                DEC::ABSOLUTE, 
-                DEC::ABSOLUTE_X, 
-
-                /* Shifts */
-                ASL::NONE_ADDRESSING, // Accumulator
-                ASL::ZERO_PAGE, 
-                ASL::ZERO_PAGE_X, 
-                ASL::ABSOLUTE, 
-                ASL::ABSOLUTE_X, 
-
-                ROL::NONE_ADDRESSING, // Accumulator
-                ROL::ZERO_PAGE, 
-                ROL::ZERO_PAGE_X, 
-                ROL::ABSOLUTE, 
-                ROL::ABSOLUTE_X, 
-
-                ROR::NONE_ADDRESSING, // Accumulator
-                ROR::ZERO_PAGE, 
-                ROR::ZERO_PAGE_X, 
-                ROR::ABSOLUTE, 
-                ROR::ABSOLUTE_X, 
-
-                INC::ZERO_PAGE,
-                INC::ZERO_PAGE_X,
-                INC::ABSOLUTE,
-                INC::ABSOLUTE_X,
-
-                DEM::ZERO_PAGE, 
-                DEM::ZERO_PAGE_X, 
-                DEM::ABSOLUTE, 
-                DEM::ABSOLUTE_X, 
-
-                DEX::NONE_ADDRESSING,
-                
-                DEY::NONE_ADDRESSING,
-
-                CMP::IMMEDIATE, 
-                CMP::ZERO_PAGE, 
-                CMP::ZERO_PAGE_X, 
-                CMP::ABSOLUTE, 
-                CMP::ABSOLUTE_X, 
-                CMP::ABSOLUTE_Y,
-                CMP::INDIRECT_X, 
-                CMP::INDIRECT_Y,
-
-                CPX::IMMEDIATE, 
-                CPX::ZERO_PAGE, 
-                CPX::ABSOLUTE, 
-                
-                CPY::IMMEDIATE,
-                CPY::ZERO_PAGE,
-                CPY::ABSOLUTE,
+// Every opcode the CPU can execute, opcodes missing from here hit the
+// "No opcode" panic in execute!
+dispatch_table!(
+    DISPATCH_TABLE,
+    dispatch,
+    {
+        /* Special */
+        BRK::NONE_ADDRESSING,
+
+        NOP::NONE_ADDRESSING,
+
+        /* Arithmetic */
+        ADC::IMMEDIATE, 
+        ADC::ZERO_PAGE, 
+        ADC::ZERO_PAGE_X, 
+        ADC::ABSOLUTE, 
+        ADC::ABSOLUTE_X, 
+        ADC::ABSOLUTE_Y,
+        ADC::INDIRECT_X, 
+        ADC::INDIRECT_Y,
+
+        SBC::IMMEDIATE,
+        SBC::ZERO_PAGE,
+        SBC::ZERO_PAGE_X,
+        SBC::ABSOLUTE,
+        SBC::ABSOLUTE_X,
+        SBC::ABSOLUTE_Y,
+        SBC::INDIRECT_X,
+        SBC::INDIRECT_Y,
+        
+        AND::IMMEDIATE, 
+        AND::ZERO_PAGE, 
+        AND::ZERO_PAGE_X, 
+        AND::ABSOLUTE, 
+        AND::ABSOLUTE_X, 
+        AND::ABSOLUTE_Y,
+        AND::INDIRECT_X, 
+        AND::INDIRECT_Y,
+
+        EOR::IMMEDIATE, 
+        EOR::ZERO_PAGE, 
+        EOR::ZERO_PAGE_X, 
+        EOR::ABSOLUTE, 
+        EOR::ABSOLUTE_X, 
+        EOR::ABSOLUTE_Y,
+        EOR::INDIRECT_X, 
+        EOR::INDIRECT_Y,
+
+        ORA::IMMEDIATE, 
+        ORA::ZERO_PAGE, 
+        ORA::ZERO_PAGE_X, 
+        ORA::ABSOLUTE, 
+        ORA::ABSOLUTE_X, 
+        ORA::ABSOLUTE_Y,
+        ORA::INDIRECT_X, 
+        ORA::INDIRECT_Y,
+
+        DEC::ZERO_PAGE, 
+        DEC::ZERO_PAGE_X, 
+        DEC::ABSOLUTE, 
+        DEC::ABSOLUTE_X, 
+
+        /* Shifts */
+        ASL::NONE_ADDRESSING, // Accumulator
+        ASL::ZERO_PAGE, 
+        ASL::ZERO_PAGE_X, 
+        ASL::ABSOLUTE, 
+        ASL::ABSOLUTE_X, 
+
+        ROL::NONE_ADDRESSING, // Accumulator
+        ROL::ZERO_PAGE, 
+        ROL::ZERO_PAGE_X, 
+        ROL::ABSOLUTE, 
+        ROL::ABSOLUTE_X, 
+
+        ROR::NONE_ADDRESSING, // Accumulator
+        ROR::ZERO_PAGE, 
+        ROR::ZERO_PAGE_X, 
+        ROR::ABSOLUTE, 
+        ROR::ABSOLUTE_X, 
+
+        INC::ZERO_PAGE,
+        INC::ZERO_PAGE_X,
+        INC::ABSOLUTE,
+        INC::ABSOLUTE_X,
+
+        DEX::NONE_ADDRESSING,
+        
+        DEY::NONE_ADDRESSING,
+
+        CMP::IMMEDIATE, 
+        CMP::ZERO_PAGE, 
+        CMP::ZERO_PAGE_X, 
+        CMP::ABSOLUTE, 
+        CMP::ABSOLUTE_X, 
+        CMP::ABSOLUTE_Y,
+        CMP::INDIRECT_X, 
+        CMP::INDIRECT_Y,
+
+        CPX::IMMEDIATE, 
+        CPX::ZERO_PAGE, 
+        CPX::ABSOLUTE, 
+        
+        CPY::IMMEDIATE,
+        CPY::ZERO_PAGE,
+        CPY::ABSOLUTE,
 
-                LSR::NONE_ADDRESSING, // Accumulator 
-                LSR::ZERO_PAGE, 
-                LSR::ZERO_PAGE_X, 
-                LSR::ABSOLUTE, 
-                LSR::ABSOLUTE_X, 
+        LSR::NONE_ADDRESSING, // Accumulator 
+        LSR::ZERO_PAGE, 
+        LSR::ZERO_PAGE_X, 
+        LSR::ABSOLUTE, 
+        LSR::ABSOLUTE_X, 
 
-                /* Branching */
-                JMP::ABSOLUTE,
-                JMP::NONE_ADDRESSING,
+        /* Branching */
+        JMP::ABSOLUTE,
+        JMP::NONE_ADDRESSING,
 
-                RTS::NONE_ADDRESSING,
+        RTS::NONE_ADDRESSING,
 
-                RTI::NONE_ADDRESSING,
+        RTI::NONE_ADDRESSING,
 
-                JSR::ABSOLUTE,
+        JSR::ABSOLUTE,
 
-                BCC::NONE_ADDRESSING,
+        BCC::NONE_ADDRESSING,
 
-                BCS::NONE_ADDRESSING,
+        BCS::NONE_ADDRESSING,
 
-                BEQ::NONE_ADDRESSING,
+        BEQ::NONE_ADDRESSING,
 
-                BMI::NONE_ADDRESSING,
+        BMI::NONE_ADDRESSING,
 
-                BNE::NONE_ADDRESSING,
+        BNE::NONE_ADDRESSING,
 
-                BPL::NONE_ADDRESSING,
+        BPL::NONE_ADDRESSING,
 
-                BVC::NONE_ADDRESSING,
+        BVC::NONE_ADDRESSING,
 
-                BVS::NONE_ADDRESSING,
+        BVS::NONE_ADDRESSING,
 
-                BIT::ZERO_PAGE,
-                BIT::ABSOLUTE,
+        BIT::ZERO_PAGE,
+        BIT::ABSOLUTE,
 
-                /* Stores & Loads */
-                LDA::IMMEDIATE,
-                LDA::ZERO_PAGE,
-                LDA::ZERO_PAGE_X,
-                LDA::ABSOLUTE,
-                LDA::ABSOLUTE_X,
-                LDA::ABSOLUTE_Y,
-                LDA::INDIRECT_X,
-                LDA::INDIRECT_Y,
+        /* Stores & Loads */
+        LDA::IMMEDIATE,
+        LDA::ZERO_PAGE,
+        LDA::ZERO_PAGE_X,
+        LDA::ABSOLUTE,
+        LDA::ABSOLUTE_X,
+        LDA::ABSOLUTE_Y,
+        LDA::INDIRECT_X,
+        LDA::INDIRECT_Y,
 
-                LDX::IMMEDIATE,
-                LDX::ZERO_PAGE,
-                LDX::ZERO_PAGE_Y,
-                LDX::ABSOLUTE,
-                LDX::ABSOLUTE_Y,
+        LDX::IMMEDIATE,
+        LDX::ZERO_PAGE,
+        LDX::ZERO_PAGE_Y,
+        LDX::ABSOLUTE,
+        LDX::ABSOLUTE_Y,
 
-                LDY::IMMEDIATE,
-                LDY::ZERO_PAGE,
-                LDY::ZERO_PAGE_X,
-                LDY::ABSOLUTE,
-                LDY::ABSOLUTE_X,
+        LDY::IMMEDIATE,
+        LDY::ZERO_PAGE,
+        LDY::ZERO_PAGE_X,
+        LDY::ABSOLUTE,
+        LDY::ABSOLUTE_X,
 
-                STA::ZERO_PAGE,
-                STA::ZERO_PAGE_X,
-                STA::ABSOLUTE,
-                STA::ABSOLUTE_X,
-                STA::ABSOLUTE_Y,
-                STA::INDIRECT_X,
-                STA::INDIRECT_Y,
+        STA::ZERO_PAGE,
+        STA::ZERO_PAGE_X,
+        STA::ABSOLUTE,
+        STA::ABSOLUTE_X,
+        STA::ABSOLUTE_Y,
+        STA::INDIRECT_X,
+        STA::INDIRECT_Y,
 
-                STX::ZERO_PAGE,
-                STX::ZERO_PAGE_X,
-                STX::ABSOLUTE,
+        STX::ZERO_PAGE,
+        STX::ZERO_PAGE_Y,
+        STX::ABSOLUTE,
 
-                STY::ZERO_PAGE,
-                STY::ZERO_PAGE_X,
-                STY::ABSOLUTE,
+        STY::ZERO_PAGE,
+        STY::ZERO_PAGE_X,
+        STY::ABSOLUTE,
 
-                /* Flags Clear */
-                CLD::NONE_ADDRESSING,
+        /* Flags Clear */
+        CLD::NONE_ADDRESSING,
 
-                CLI::NONE_ADDRESSING,
+        CLI::NONE_ADDRESSING,
 
-                CLV::NONE_ADDRESSING,
+        CLV::NONE_ADDRESSING,
 
-                CLC::NONE_ADDRESSING,
+        CLC::NONE_ADDRESSING,
 
-                TAX::NONE_ADDRESSING,
+        TAX::NONE_ADDRESSING,
 
-                TAY::NONE_ADDRESSING,
+        TAY::NONE_ADDRESSING,
 
-                INX::NONE_ADDRESSING,
+        INX::NONE_ADDRESSING,
 
-                INY::NONE_ADDRESSING,
+        INY::NONE_ADDRESSING,
 
-                SEC::NONE_ADDRESSING,
+        SEC::NONE_ADDRESSING,
 
-                SEI::NONE_ADDRESSING,
+        SEI::NONE_ADDRESSING,
 
-                SED::NONE_ADDRESSING,
+        SED::NONE_ADDRESSING,
 
-                TSX::NONE_ADDRESSING,
+        TSX::NONE_ADDRESSING,
 
-                TXA::NONE_ADDRESSING,
+        TXA::NONE_ADDRESSING,
 
-                TXS::NONE_ADDRESSING,
+        TXS::NONE_ADDRESSING,
 
-                TYA::NONE_ADDRESSING,
+        TYA::NONE_ADDRESSING,
 
-                /* Stack */
-                PHA::NONE_ADDRESSING,
+        /* Stack */
+        PHA::NONE_ADDRESSING,
 
-                PHP::NONE_ADDRESSING,
+        PHP::NONE_ADDRESSING,
 
-                PLA::NONE_ADDRESSING,
+        PLA::NONE_ADDRESSING,
 
-                PLP::NONE_ADDRESSING,
-            }
-        );
+        PLP::NONE_ADDRESSING,
+    }
+);
+
+// Undocumented opcodes, only dispatched while illegal_opcodes is set
+dispatch_table!(
+    ILLEGAL_DISPATCH_TABLE,
+    dispatch_illegal,
+    {
+        illegal::LAX::ZERO_PAGE,
+        illegal::LAX::ZERO_PAGE_Y,
+        illegal::LAX::ABSOLUTE,
+        illegal::LAX::ABSOLUTE_Y,
+        illegal::LAX::INDIRECT_X,
+        illegal::LAX::INDIRECT_Y,
+
+        illegal::SAX::ZERO_PAGE,
+        illegal::SAX::ZERO_PAGE_Y,
+        illegal::SAX::ABSOLUTE,
+        illegal::SAX::INDIRECT_X,
+
+        illegal::DCP::ZERO_PAGE,
+        illegal::DCP::ZERO_PAGE_X,
+        illegal::DCP::ABSOLUTE,
+        illegal::DCP::ABSOLUTE_X,
+        illegal::DCP::ABSOLUTE_Y,
+        illegal::DCP::INDIRECT_X,
+        illegal::DCP::INDIRECT_Y,
+
+        illegal::ISB::ZERO_PAGE,
+        illegal::ISB::ZERO_PAGE_X,
+        illegal::ISB::ABSOLUTE,
+        illegal::ISB::ABSOLUTE_X,
+        illegal::ISB::ABSOLUTE_Y,
+        illegal::ISB::INDIRECT_X,
+        illegal::ISB::INDIRECT_Y,
+
+        illegal::SLO::ZERO_PAGE,
+        illegal::SLO::ZERO_PAGE_X,
+        illegal::SLO::ABSOLUTE,
+        illegal::SLO::ABSOLUTE_X,
+        illegal::SLO::ABSOLUTE_Y,
+        illegal::SLO::INDIRECT_X,
+        illegal::SLO::INDIRECT_Y,
+
+        illegal::RLA::ZERO_PAGE,
+        illegal::RLA::ZERO_PAGE_X,
+        illegal::RLA::ABSOLUTE,
+        illegal::RLA::ABSOLUTE_X,
+        illegal::RLA::ABSOLUTE_Y,
+        illegal::RLA::INDIRECT_X,
+        illegal::RLA::INDIRECT_Y,
+
+        illegal::SRE::ZERO_PAGE,
+        illegal::SRE::ZERO_PAGE_X,
+        illegal::SRE::ABSOLUTE,
+        illegal::SRE::ABSOLUTE_X,
+        illegal::SRE::ABSOLUTE_Y,
+        illegal::SRE::INDIRECT_X,
+        illegal::SRE::INDIRECT_Y,
+
+        illegal::RRA::ZERO_PAGE,
+        illegal::RRA::ZERO_PAGE_X,
+        illegal::RRA::ABSOLUTE,
+        illegal::RRA::ABSOLUTE_X,
+        illegal::RRA::ABSOLUTE_Y,
+        illegal::RRA::INDIRECT_X,
+        illegal::RRA::INDIRECT_Y,
+
+        illegal::SBC::IMMEDIATE,
+
+        illegal::NOP::NONE_ADDRESSING_1A,
+        illegal::NOP::NONE_ADDRESSING_3A,
+        illegal::NOP::NONE_ADDRESSING_5A,
+        illegal::NOP::NONE_ADDRESSING_7A,
+        illegal::NOP::NONE_ADDRESSING_DA,
+        illegal::NOP::NONE_ADDRESSING_FA,
+        illegal::NOP::IMMEDIATE_80,
+        illegal::NOP::IMMEDIATE_82,
+        illegal::NOP::IMMEDIATE_89,
+        illegal::NOP::IMMEDIATE_C2,
+        illegal::NOP::IMMEDIATE_E2,
+        illegal::NOP::ZERO_PAGE_04,
+        illegal::NOP::ZERO_PAGE_44,
+        illegal::NOP::ZERO_PAGE_64,
+        illegal::NOP::ZERO_PAGE_X_14,
+        illegal::NOP::ZERO_PAGE_X_34,
+        illegal::NOP::ZERO_PAGE_X_54,
+        illegal::NOP::ZERO_PAGE_X_74,
+        illegal::NOP::ZERO_PAGE_X_D4,
+        illegal::NOP::ZERO_PAGE_X_F4,
+        illegal::NOP::ABSOLUTE,
+        illegal::NOP::ABSOLUTE_X_1C,
+        illegal::NOP::ABSOLUTE_X_3C,
+        illegal::NOP::ABSOLUTE_X_5C,
+        illegal::NOP::ABSOLUTE_X_7C,
+        illegal::NOP::ABSOLUTE_X_DC,
+        illegal::NOP::ABSOLUTE_X_FC,
+
+        illegal::JAM::NONE_ADDRESSING_02,
+        illegal::JAM::NONE_ADDRESSING_12,
+        illegal::JAM::NONE_ADDRESSING_22,
+        illegal::JAM::NONE_ADDRESSING_32,
+        illegal::JAM::NONE_ADDRESSING_42,
+        illegal::JAM::NONE_ADDRESSING_52,
+        illegal::JAM::NONE_ADDRESSING_62,
+        illegal::JAM::NONE_ADDRESSING_72,
+        illegal::JAM::NONE_ADDRESSING_92,
+        illegal::JAM::NONE_ADDRESSING_B2,
+        illegal::JAM::NONE_ADDRESSING_D2,
+        illegal::JAM::NONE_ADDRESSING_F2,
     }
-}
\ No newline at end of file
+);
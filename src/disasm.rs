@@ -0,0 +1,162 @@
+use std::fmt;
+
+use crate::opcodes::{self, AddressingMode, Opcode};
+
+/// How an opcode's operand is written in assembly. NONE_ADDRESSING covers
+/// implied, accumulator, relative and indirect opcodes so those are told
+/// apart by the opcode's length and name.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Syntax {
+    Implied,
+    Accumulator,
+    Relative,
+    Indirect,
+    Mode(AddressingMode),
+}
+
+pub fn syntax(opcode: &Opcode) -> Syntax {
+    return match (opcode.mode, opcode.len) {
+        (AddressingMode::NONE_ADDRESSING, 2) => Syntax::Relative,
+        (AddressingMode::NONE_ADDRESSING, 3) => Syntax::Indirect,
+        (AddressingMode::NONE_ADDRESSING, _) => match opcode.name {
+            "ASL" | "LSR" | "ROL" | "ROR" => Syntax::Accumulator,
+            _ => Syntax::Implied,
+        },
+        (mode, _) => Syntax::Mode(mode),
+    };
+}
+
+/// A single disassembled instruction
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.bytes.iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        write!(f, "{:04X}  {:<8}  {}", self.addr, bytes, self.text)
+    }
+}
+
+/// Disassembles every instruction in bytes, the first byte being at base
+pub fn disassemble(bytes: &[u8], base: u16) -> Vec<Instruction> {
+    let mut result = vec![];
+    let mut offset = 0;
+
+    while let Some(instruction) = disassemble_one(&bytes[offset..], base.wrapping_add(offset as u16)) {
+        offset += instruction.bytes.len();
+        result.push(instruction);
+    }
+
+    return result;
+}
+
+/// Disassembles the instruction at the start of bytes, None when bytes is
+/// empty. Unknown opcodes, or ones cut short by the end of bytes, come out
+/// as `.byte $xx`.
+pub fn disassemble_one(bytes: &[u8], addr: u16) -> Option<Instruction> {
+    let first = *bytes.first()?;
+    let opcode = match opcodes::lookup(first) {
+        Some(opcode) if opcode.len as usize <= bytes.len() => opcode,
+        _ => {
+            return Some(Instruction {
+                addr,
+                bytes: vec![first],
+                text: format!(".byte ${:02X}", first),
+            });
+        }
+    };
+
+    let bytes = bytes[..opcode.len as usize].to_vec();
+    let operand = format_operand(opcode, &bytes, addr);
+    let text = match operand.is_empty() {
+        true => opcode.name.to_string(),
+        false => format!("{} {}", opcode.name, operand),
+    };
+
+    return Some(Instruction { addr, bytes, text });
+}
+
+fn format_operand(opcode: &Opcode, bytes: &[u8], addr: u16) -> String {
+    use AddressingMode::*;
+
+    let byte = || bytes[1];
+    let word = || u16::from_le_bytes([bytes[1], bytes[2]]);
+
+    return match syntax(opcode) {
+        Syntax::Implied => String::new(),
+        Syntax::Accumulator => "A".to_string(),
+        Syntax::Relative => {
+            // Offset is relative to the instruction after the branch
+            let target = addr.wrapping_add(2).wrapping_add(byte() as i8 as u16);
+            format!("${:04X}", target)
+        }
+        Syntax::Indirect => format!("(${:04X})", word()),
+        Syntax::Mode(mode) => match mode {
+            IMMEDIATE => format!("#${:02X}", byte()),
+            ZERO_PAGE => format!("${:02X}", byte()),
+            ZERO_PAGE_X => format!("${:02X},X", byte()),
+            ZERO_PAGE_Y => format!("${:02X},Y", byte()),
+            ABSOLUTE => format!("${:04X}", word()),
+            ABSOLUTE_X => format!("${:04X},X", word()),
+            ABSOLUTE_Y => format!("${:04X},Y", word()),
+            INDIRECT_X => format!("(${:02X},X)", byte()),
+            INDIRECT_Y => format!("(${:02X}),Y", byte()),
+            NONE_ADDRESSING => String::new(),
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let program = [
+            0xB1, 0x20,       // LDA ($20),Y
+            0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+            0x0A,             // ASL A
+            0x9D, 0x00, 0x02, // STA $0200,X
+            0xD0, 0xF5,       // BNE back to the start
+            0xA7, 0x10,       // LAX $10
+            0x8B,             // Unknown
+            0xA9,             // LDA # cut short
+        ];
+
+        let text: Vec<String> = disassemble(&program, 0x8000)
+            .iter()
+            .map(|instruction| instruction.text.clone())
+            .collect();
+
+        assert_eq!(text, vec![
+            "LDA ($20),Y",
+            "JMP ($FFFC)",
+            "ASL A",
+            "STA $0200,X",
+            "BNE $8000",
+            "LAX $10",
+            ".byte $8B",
+            ".byte $A9",
+        ]);
+    }
+
+    #[test]
+    fn test_display() {
+        let instruction = disassemble_one(&[0x4C, 0xF5, 0xC5], 0xC000).unwrap();
+
+        assert_eq!(instruction.to_string(), "C000  4C F5 C5  JMP $C5F5");
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(disassemble_one(&[], 0x8000).is_none());
+        assert!(disassemble(&[], 0x8000).is_empty());
+    }
+}
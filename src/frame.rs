@@ -0,0 +1,35 @@
+/// A rendered picture as packed RGB24, ready to be copied into a texture
+/// by whichever frontend is displaying it.
+pub struct Frame {
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub const WIDTH: usize = 256;
+    pub const HEIGHT: usize = 240;
+    // Bytes per row, for uploading to a texture
+    pub const PITCH: usize = Frame::WIDTH * 3;
+
+    pub fn new() -> Self {
+        Frame {
+            data: vec![0; Frame::WIDTH * Frame::HEIGHT * 3],
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        if x >= Frame::WIDTH || y >= Frame::HEIGHT {
+            return;
+        }
+
+        let base = y * Frame::PITCH + x * 3;
+        self.data[base] = rgb.0;
+        self.data[base + 1] = rgb.1;
+        self.data[base + 2] = rgb.2;
+    }
+
+    #[cfg(test)]
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = y * Frame::PITCH + x * 3;
+        return (self.data[base], self.data[base + 1], self.data[base + 2]);
+    }
+}
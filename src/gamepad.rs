@@ -1 +1,125 @@
-pub static BUTTONS: u8 = 0x0000 << 1;
\ No newline at end of file
+use bitflags::bitflags;
+
+// Writing bit 0 strobes both controllers, reads shift out port 1
+pub const JOYPAD1: u16 = 0x4016;
+// Reads shift out port 2, writes go to the APU frame counter
+pub const JOYPAD2: u16 = 0x4017;
+
+// Bits the ports leave undriven, they keep what was last on the data bus,
+// the high byte of the address for a plain LDA $4016. Of the rest only bit
+// 0 is driven by a standard controller.
+pub const OPEN_BUS_MASK: u8 = 0b1110_0000;
+
+bitflags! {
+    // In the order the shift register hands them out, A first
+    #[derive(PartialEq, Eq)]
+    #[derive(Clone, Copy)]
+    pub struct Buttons: u8 {
+        const A = 0b0000_0001;
+        const B = 0b0000_0010;
+        const Select = 0b0000_0100;
+        const Start = 0b0000_1000;
+        const Up = 0b0001_0000;
+        const Down = 0b0010_0000;
+        const Left = 0b0100_0000;
+        const Right = 0b1000_0000;
+    }
+}
+
+/// Standard controller. While the strobe is high the buttons are latched
+/// into an 8 bit shift register, once it drops every read shifts out the
+/// next one. Official pads read 1 after the eighth.
+pub struct Gamepad {
+    // Held down right now
+    pub buttons: Buttons,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Gamepad {
+
+    pub fn new() -> Self {
+        Gamepad {
+            buttons: Buttons::empty(),
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons.bits();
+        }
+    }
+
+    pub fn peek(&self) -> u8 {
+        // A strobe held high keeps reloading, reads only ever see A
+        let bit = match self.strobe {
+            true => self.buttons.bits() & 1,
+            false => self.shift & 1,
+        };
+        return bit;
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let data = self.peek();
+        if !self.strobe {
+            self.shift = (self.shift >> 1) | 0b1000_0000;
+        }
+        return data;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_all(gamepad: &mut Gamepad, count: usize) -> Vec<u8> {
+        return (0..count).map(|_| gamepad.read() & 1).collect();
+    }
+
+    #[test]
+    fn test_reads_buttons_in_order() {
+        let mut gamepad = Gamepad::new();
+        gamepad.buttons = Buttons::A | Buttons::Start | Buttons::Left;
+        gamepad.write(1);
+        gamepad.write(0);
+
+        assert_eq!(read_all(&mut gamepad, 8), vec![1, 0, 0, 1, 0, 0, 1, 0]);
+        // Past the eighth read
+        assert_eq!(read_all(&mut gamepad, 4), vec![1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_latched_on_strobe() {
+        let mut gamepad = Gamepad::new();
+        gamepad.buttons = Buttons::B;
+        gamepad.write(1);
+        gamepad.write(0);
+
+        // Pressed after the latch, not seen until the next strobe
+        gamepad.buttons = Buttons::A;
+        assert_eq!(read_all(&mut gamepad, 2), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_strobe_high_returns_a() {
+        let mut gamepad = Gamepad::new();
+        gamepad.buttons = Buttons::A | Buttons::B;
+        gamepad.write(1);
+
+        assert_eq!(read_all(&mut gamepad, 3), vec![1, 1, 1]);
+        gamepad.buttons = Buttons::B;
+        assert_eq!(gamepad.read() & 1, 0);
+    }
+
+    #[test]
+    fn test_drives_bit_0_only() {
+        let mut gamepad = Gamepad::new();
+        assert_eq!(gamepad.peek(), 0);
+        gamepad.buttons = Buttons::all();
+        gamepad.write(1);
+        assert_eq!(gamepad.read(), 1);
+    }
+}
@@ -1,15 +1,41 @@
+mod apu;
+mod audio;
+mod axrom;
+mod bus;
+mod cartridge;
+mod cnrom;
 mod cpu;
+mod disasm;
+mod frame;
 mod opcodes;
 mod gamepad;
+mod mapper;
+mod mmc1;
+mod mmc3;
+mod palette;
+mod ppu;
+mod render;
+mod save;
+mod trace;
+mod uxrom;
 
-use std::{time::{SystemTime}};
+use std::{path::Path, time::{SystemTime}};
 
-use cpu::{Memory, CPU};
+use audio::{AudioOutput, NullAudio, RateControl, Resampler, SdlAudio};
+use cartridge::Rom;
+use cpu::{CpuError, Memory, CPU};
+use frame::Frame;
+use gamepad::Buttons;
+use palette::Palette;
 use rand::Rng;
+use save::SaveFile;
 use sdl2::{event::Event, keyboard::Keycode, pixels::{Color, PixelFormat, PixelFormatEnum}, EventPump};
 use spin_sleep::SpinSleeper;
 
 const FRAME_TIMING: f64 = 1_000_000_000.0 / 60.0;
+// Disassembled from the counter when the CPU fails, 3 bytes at most each
+const CRASH_REPORT_INSTRUCTIONS: usize = 4;
+const CRASH_REPORT_BYTES: u16 = 3 * CRASH_REPORT_INSTRUCTIONS as u16;
 
 fn main() {
 
@@ -19,10 +45,18 @@ fn main() {
     // PPU.evaluate()
     // PPU.Render()
     // PPU.Scroll()
-    // Gamepad.listen()
-    // APU.update()
     
     let sdl_context = sdl2::init().unwrap();
+
+    // Plays the rom given on the command line, Snake when there isn't one
+    // rgboy [game.nes [palette.pal]]
+    match std::env::args().nth(1) {
+        Some(path) => run_rom(&sdl_context, &path, std::env::args().nth(2)),
+        None => run_snake(&sdl_context),
+    }
+}
+
+fn run_snake(sdl_context: &sdl2::Sdl) {
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
         .window("Snake!", (32 * 10) as u32, (32 * 10) as u32 )
@@ -38,6 +72,8 @@ fn main() {
         .create_texture_target(PixelFormatEnum::RGB24, 32, 32).unwrap();
 
     let mut cpu = CPU::new();
+    // Easy6502 programs use BRK to signal the end of the program
+    cpu.halt_on_brk = true;
     cpu.load_snake();
     cpu.reset_interrupt();
 
@@ -47,7 +83,7 @@ fn main() {
     let sleeper = SpinSleeper::default();
     let mut lastTime = SystemTime::now();
 
-    cpu.run_snake_with_callback(move |cpu| {
+    let result = cpu.run_snake_with_callback(move |cpu| {
         
         handle_input(cpu, &mut event_pump);
         cpu.mem_write(0xFE, rng.gen_range(1..16));
@@ -67,6 +103,184 @@ fn main() {
             lastTime = SystemTime::now();
         }
     });
+
+    if let Err(err) = result {
+        exit_with_error(&cpu, err);
+    }
+}
+
+fn run_rom(sdl_context: &sdl2::Sdl, path: &str, palette_path: Option<String>) {
+    let rom = match Rom::load(path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
+
+    let palette = match palette_path {
+        Some(palette_path) => match Palette::load(&palette_path) {
+            Ok(palette) => palette,
+            Err(err) => {
+                eprintln!("{}: {}", palette_path, err);
+                std::process::exit(1);
+            }
+        },
+        None => Palette::default(),
+    };
+
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("Rgboy", (Frame::WIDTH * 3) as u32, (Frame::HEIGHT * 3) as u32)
+        .position_centered()
+        .build().unwrap();
+
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_scale(3.0, 3.0).unwrap();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, Frame::WIDTH as u32, Frame::HEIGHT as u32)
+        .unwrap();
+
+    let mut cpu = CPU::new();
+    if let Err(err) = cpu.load_rom(rom) {
+        eprintln!("{}: {}", path, err);
+        std::process::exit(1);
+    }
+    cpu.bus.ppu.system_palette = palette;
+
+    // Battery RAM is loaded before the game gets to look at it
+    let save_path = SaveFile::path_for(path);
+    let save = SaveFile::open(&save_path, &mut *cpu.bus.cartridge.borrow_mut());
+    let mut save = match save {
+        Ok(save) => save,
+        Err(err) => {
+            eprintln!("{}: {}", save_path.display(), err);
+            std::process::exit(1);
+        }
+    };
+    cpu.reset_interrupt();
+
+    let mut audio = open_audio(sdl_context);
+    let rate_control = RateControl::new(audio.sample_rate());
+    cpu.bus.apu.resampler = Resampler::new(audio.sample_rate());
+
+    let mut frames = 0;
+    // RGBOY_TRACE=1 prints every instruction as a nestest.log line
+    let tracing = std::env::var("RGBOY_TRACE").map_or(false, |trace| trace == "1");
+
+    // Presents every frame the PPU completes, vsync paces it. The save is
+    // only borrowed, a crash still gets to flush it.
+    let result = cpu.run_with_callback(|cpu| {
+        if tracing {
+            println!("{}", trace::trace(cpu));
+        }
+
+        if cpu.bus.ppu.frames == frames {
+            return;
+        }
+        frames = cpu.bus.ppu.frames;
+
+        // Steer the queue back toward its target rather than let it
+        // underrun and crackle
+        audio.queue(&cpu.bus.apu.take_samples());
+        cpu.bus.apu.resampler.rate = rate_control.rate(audio.queued());
+
+        if let Some(save) = save.as_mut() {
+            if let Err(err) = save.tick(&*cpu.bus.cartridge.borrow()) {
+                eprintln!("{}: {}", save_path.display(), err);
+            }
+        }
+
+        texture.update(None, &cpu.bus.ppu.frame.data, Frame::PITCH).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    flush_save(&mut save, &save_path, cpu);
+                    std::process::exit(0)
+                },
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                    if let Some(button) = key_button(keycode) {
+                        cpu.bus.gamepads[0].buttons.insert(button);
+                    }
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(button) = key_button(keycode) {
+                        cpu.bus.gamepads[0].buttons.remove(button);
+                    }
+                }
+                _ => {/* Do nothing! */}
+            }
+        }
+    });
+
+    if let Err(err) = result {
+        flush_save(&mut save, &save_path, &cpu);
+        exit_with_error(&cpu, err);
+    }
+}
+
+// Writes out battery RAM before exiting, failing to is only reported
+fn flush_save(save: &mut Option<SaveFile>, path: &Path, cpu: &CPU) {
+    if let Some(save) = save.as_mut() {
+        if let Err(err) = save.flush(&*cpu.bus.cartridge.borrow()) {
+            eprintln!("{}: {}", path.display(), err);
+        }
+    }
+}
+
+// Keyboard layout of controller 1
+fn key_button(keycode: Keycode) -> Option<Buttons> {
+    return match keycode {
+        Keycode::X => Some(Buttons::A),
+        Keycode::Z => Some(Buttons::B),
+        Keycode::RShift => Some(Buttons::Select),
+        Keycode::Return => Some(Buttons::Start),
+        Keycode::Up => Some(Buttons::Up),
+        Keycode::Down => Some(Buttons::Down),
+        Keycode::Left => Some(Buttons::Left),
+        Keycode::Right => Some(Buttons::Right),
+        _ => None,
+    };
+}
+
+// SDL audio, or nothing when RGBOY_AUDIO=null or no device opens
+fn open_audio(sdl_context: &sdl2::Sdl) -> Box<dyn AudioOutput> {
+    let null = NullAudio { sample_rate: audio::DEFAULT_SAMPLE_RATE };
+    if std::env::var("RGBOY_AUDIO").map_or(false, |backend| backend == "null") {
+        return Box::new(null);
+    }
+
+    return match SdlAudio::open(sdl_context) {
+        Ok(audio) => Box::new(audio),
+        Err(err) => {
+            eprintln!("audio disabled: {}", err);
+            Box::new(null)
+        }
+    };
+}
+
+fn exit_with_error(cpu: &CPU, err: CpuError) {
+    eprintln!("{}", err);
+
+    // The failing instruction and the few after it
+    let bytes: Vec<u8> = (0..CRASH_REPORT_BYTES)
+        .map(|offset| cpu.mem_peek(cpu.counter.wrapping_add(offset)))
+        .collect();
+    for instruction in disasm::disassemble(&bytes, cpu.counter).iter().take(CRASH_REPORT_INSTRUCTIONS) {
+        eprintln!("{}", instruction);
+    }
+
+    // Keep the address space around to debug the crash
+    if let Ok(file) = std::fs::File::create("memory.txt") {
+        _ = cpu.write_memory_dump(file);
+    }
+    std::process::exit(1);
 }
 
 fn handle_input(cpu: &mut CPU, event_pump: &mut EventPump) {
@@ -111,7 +325,7 @@ fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     let mut update = false;
     
     for i in 0x0200..0x600 {
-        let color_idx = cpu.mem_read(i as u16);
+        let color_idx = cpu.mem_peek(i as u16);
         let (b1, b2, b3) = to_color(color_idx).rgb();
 
         if frame[frame_idx] != b1 
@@ -137,7 +351,7 @@ mod test {
 
     use opcodes::{ADC, CLC, SBC, SEC};
 
-    use crate::{cpu::Flag, opcodes::{INX, BRK, CPY, LDA, TAX}};
+    use crate::{cpu::Flag, opcodes::{INX, BRK, CPY, LDA, LDX, STA, BEQ, BNE, TAX}};
 
     use super::*;
 
@@ -164,13 +378,14 @@ mod test {
 #[test]
 fn test_0xa9_lda_immediate_load_data() {
     let mut cpu: CPU = CPU::new();
+    cpu.halt_on_brk = true;
     let program = vec![
         LDA::IMMEDIATE::VALUE, 
         0x05, 
         BRK::NONE_ADDRESSING::VALUE
     ];
 
-    cpu.load_and_run(program);
+    cpu.load_and_run(program).unwrap();
 
     assert!(
         cpu.status & Flag::from_bits_truncate(0b0000_0010) 
@@ -183,13 +398,14 @@ fn test_0xa9_lda_immediate_load_data() {
 #[test]
 fn test_0xa9_lda_zero_flag() {
     let mut cpu: CPU = CPU::new();
+    cpu.halt_on_brk = true;
     let program = vec![
         LDA::IMMEDIATE::VALUE, 
         0x00, 
         BRK::NONE_ADDRESSING::VALUE
     ];
 
-    cpu.load_and_run(program);
+    cpu.load_and_run(program).unwrap();
     
     assert!(
         cpu.status & Flag::from_bits_truncate(0b0000_0010) 
@@ -199,6 +415,7 @@ fn test_0xa9_lda_zero_flag() {
 #[test]
 fn test_0xaa_tax_move_a_to_x() {
         let mut cpu: CPU = CPU::new();
+        cpu.halt_on_brk = true;
         let program = vec![
             TAX::NONE_ADDRESSING::VALUE, 
             BRK::NONE_ADDRESSING::VALUE
@@ -207,7 +424,7 @@ fn test_0xaa_tax_move_a_to_x() {
         cpu.load(program);
         cpu.reset_interrupt();
         cpu.register_a = 10;
-        cpu.run();
+        cpu.run().unwrap();
     
         assert_eq!(cpu.register_x, 10)
 }
@@ -215,6 +432,7 @@ fn test_0xaa_tax_move_a_to_x() {
 #[test]
 fn test_5_ops_working_together() {
         let mut cpu: CPU = CPU::new();
+        cpu.halt_on_brk = true;
         
         let program = vec![
             LDA::IMMEDIATE::VALUE, 
@@ -224,7 +442,7 @@ fn test_5_ops_working_together() {
             BRK::NONE_ADDRESSING::VALUE
         ];
 
-        cpu.load_and_run(program);
+        cpu.load_and_run(program).unwrap();
 
         assert_eq!(cpu.register_x, 0xC1)    
 }
@@ -232,6 +450,7 @@ fn test_5_ops_working_together() {
 #[test]
 fn test_int_overflow() {
     let mut cpu: CPU = CPU::new();
+    cpu.halt_on_brk = true;
     let program = vec![
         INX::NONE_ADDRESSING::VALUE, 
         INX::NONE_ADDRESSING::VALUE, 
@@ -241,11 +460,687 @@ fn test_int_overflow() {
     cpu.load(program);
     cpu.reset_interrupt();
     cpu.register_x = 0xff;
-    cpu.run();
+    cpu.run().unwrap();
 
     assert_eq!(cpu.register_x, 1)
 }
 
+#[test]
+fn test_bus_ram_mirroring() {
+    let mut cpu: CPU = CPU::new();
+
+    cpu.mem_write(0x0012, 0xAB);
+    assert_eq!(cpu.mem_read(0x0812), 0xAB);
+    assert_eq!(cpu.mem_read(0x1012), 0xAB);
+    assert_eq!(cpu.mem_read(0x1812), 0xAB);
+
+    cpu.mem_write(0x1FFF, 0xCD);
+    assert_eq!(cpu.mem_read(0x07FF), 0xCD);
+}
+
+#[test]
+fn test_bus_ppu_register_mirroring() {
+    let mut cpu: CPU = CPU::new();
+
+    // PPUADDR through 0x3FFE and 0x200E, PPUDATA through 0x2FFF
+    cpu.mem_write(0x3FFE, 0x23);
+    cpu.mem_write(0x200E, 0x45);
+    cpu.mem_write(0x2FFF, 0x99);
+
+    assert_eq!(cpu.bus.ppu.read_vram(0x2345), 0x99);
+    assert_eq!(cpu.bus.ppu.v, 0x2346);
+
+    // Peeking PPUSTATUS leaves VBlank alone, reading it clears it
+    cpu.bus.ppu.status.insert(ppu::Status::VBlank);
+    assert_eq!(cpu.mem_peek(0x3002) & 0x80, 0x80);
+    assert_eq!(cpu.mem_read(0x3002) & 0x80, 0x80);
+    assert_eq!(cpu.mem_read(0x2002) & 0x80, 0);
+}
+
+#[test]
+fn test_load_rom_reset_vector() {
+    use crate::cartridge::RomBuilder;
+
+    let mut prg_rom = vec![0; 16 * 1024];
+    // Reset vector at the end of the 16 KiB bank, mirrored to 0xFFFC
+    prg_rom[0x3FFC] = 0x34;
+    prg_rom[0x3FFD] = 0xC2;
+
+    let mut cpu: CPU = CPU::new();
+    cpu.load_rom(RomBuilder::new(0).prg_rom(prg_rom).chr_rom(vec![0; 8 * 1024]).build()).unwrap();
+    cpu.reset_interrupt();
+
+    assert_eq!(cpu.counter, 0xC234);
+}
+
+#[test]
+fn test_cycles_page_crossing() {
+    let mut cpu: CPU = CPU::new();
+    cpu.halt_on_brk = true;
+    let program = vec![
+        LDX::IMMEDIATE::VALUE, 0x01,      // 2
+        LDA::ABSOLUTE_X::VALUE, 0x10, 0x02, // 4, same page
+        LDA::ABSOLUTE_X::VALUE, 0xFF, 0x02, // 4 +1 page crossed
+        STA::ABSOLUTE_X::VALUE, 0xFF, 0x02, // 5, no penalty for stores
+        BRK::NONE_ADDRESSING::VALUE
+    ];
+
+    cpu.load_and_run(program).unwrap();
+
+    assert_eq!(cpu.cycles, 2 + 4 + 5 + 5);
+}
+
+#[test]
+fn test_cycles_branch() {
+    let mut cpu: CPU = CPU::new();
+    cpu.halt_on_brk = true;
+    let program = vec![
+        LDX::IMMEDIATE::VALUE, 0x00,      // 2
+        BNE::NONE_ADDRESSING::VALUE, 0x10, // 2, not taken
+        BEQ::NONE_ADDRESSING::VALUE, 0x00, // 2 +1 taken
+        BEQ::NONE_ADDRESSING::VALUE, 0x80, // 2 +1 taken +1 new page
+    ];
+
+    cpu.load(program);
+    cpu.reset_interrupt();
+    // Branch lands on 0x7F88, drop a BRK there to stop
+    cpu.mem_write(0x7F88, BRK::NONE_ADDRESSING::VALUE);
+    cpu.run().unwrap();
+
+    assert_eq!(cpu.counter, 0x7F89);
+    assert_eq!(cpu.cycles, 2 + 2 + 3 + 4);
+}
+
+#[test]
+fn test_brk_interrupt() {
+    use crate::opcodes::RTI;
+
+    let mut cpu: CPU = CPU::new();
+    let program = vec![
+        BRK::NONE_ADDRESSING::VALUE,
+        0x00, // Padding byte skipped by BRK
+        INX::NONE_ADDRESSING::VALUE,
+    ];
+
+    cpu.load(program);
+    // IRQ / BRK handler at 0x9000 sets A then returns
+    cpu.mem_write_u16(0xFFFE, 0x9000);
+    cpu.mem_write(0x9000, LDA::IMMEDIATE::VALUE);
+    cpu.mem_write(0x9001, 0x42);
+    cpu.mem_write(0x9002, RTI::NONE_ADDRESSING::VALUE);
+    cpu.reset_interrupt();
+
+    cpu.run_snake_with_callback(|cpu| {
+        if cpu.register_x == 1 {
+            assert_eq!(cpu.register_a, 0x42);
+            assert_eq!(cpu.counter, 0x8003);
+            // Status pushed by BRK had both B bits set
+            assert_eq!(cpu.mem_read(0x01FB) & 0b0011_0000, 0b0011_0000);
+            assert!(!cpu.status.contains(Flag::BreakCommand));
+            // Mark the test as done through a halting BRK
+            cpu.halt_on_brk = true;
+            cpu.counter = 0x8000;
+        }
+    }).unwrap();
+}
+
+#[test]
+fn test_nmi_and_irq() {
+    use crate::opcodes::{NOP, SEI, CLI, INC, RTI};
+
+    let mut cpu: CPU = CPU::new();
+    cpu.halt_on_brk = true;
+    let program = vec![
+        NOP::NONE_ADDRESSING::VALUE,
+        CLI::NONE_ADDRESSING::VALUE,
+        NOP::NONE_ADDRESSING::VALUE,
+        SEI::NONE_ADDRESSING::VALUE,
+        BRK::NONE_ADDRESSING::VALUE,
+    ];
+
+    cpu.load(program);
+    cpu.mem_write_u16(0xFFFA, 0x9000);
+    cpu.mem_write_u16(0xFFFE, 0x9100);
+    // Both handlers just count and return
+    cpu.mem_write(0x9000, INX::NONE_ADDRESSING::VALUE);
+    cpu.mem_write(0x9001, RTI::NONE_ADDRESSING::VALUE);
+    cpu.mem_write(0x9100, INC::ZERO_PAGE::VALUE);
+    cpu.mem_write(0x9101, 0x10);
+    cpu.mem_write(0x9102, RTI::NONE_ADDRESSING::VALUE);
+    cpu.reset_interrupt();
+
+    // NMI ignores InterruptDisable and only fires once per edge
+    cpu.trigger_nmi();
+    cpu.run_snake_with_callback(|cpu| {
+        // IRQ is masked until CLI and serviced while the line is held
+        if cpu.register_x == 1 && cpu.mem_read(0x10) == 0 {
+            cpu.assert_irq();
+        }
+        if cpu.mem_read(0x10) == 2 {
+            cpu.release_irq();
+        }
+    }).unwrap();
+
+    assert_eq!(cpu.register_x, 1);
+    assert_eq!(cpu.mem_read(0x10), 2);
+}
+
+// Every official 6502 opcode, one row per instruction
+const OFFICIAL_OPCODES: [&[u8]; 56] = [
+    &[0x69, 0x65, 0x75, 0x6D, 0x7D, 0x79, 0x61, 0x71], // ADC
+    &[0x29, 0x25, 0x35, 0x2D, 0x3D, 0x39, 0x21, 0x31], // AND
+    &[0x0A, 0x06, 0x16, 0x0E, 0x1E], // ASL
+    &[0x90], &[0xB0], &[0xF0], // BCC BCS BEQ
+    &[0x24, 0x2C], // BIT
+    &[0x30], &[0xD0], &[0x10], // BMI BNE BPL
+    &[0x00], // BRK
+    &[0x50], &[0x70], // BVC BVS
+    &[0x18], &[0xD8], &[0x58], &[0xB8], // CLC CLD CLI CLV
+    &[0xC9, 0xC5, 0xD5, 0xCD, 0xDD, 0xD9, 0xC1, 0xD1], // CMP
+    &[0xE0, 0xE4, 0xEC], // CPX
+    &[0xC0, 0xC4, 0xCC], // CPY
+    &[0xC6, 0xD6, 0xCE, 0xDE], // DEC
+    &[0xCA], &[0x88], // DEX DEY
+    &[0x49, 0x45, 0x55, 0x4D, 0x5D, 0x59, 0x41, 0x51], // EOR
+    &[0xE6, 0xF6, 0xEE, 0xFE], // INC
+    &[0xE8], &[0xC8], // INX INY
+    &[0x4C, 0x6C], // JMP
+    &[0x20], // JSR
+    &[0xA9, 0xA5, 0xB5, 0xAD, 0xBD, 0xB9, 0xA1, 0xB1], // LDA
+    &[0xA2, 0xA6, 0xB6, 0xAE, 0xBE], // LDX
+    &[0xA0, 0xA4, 0xB4, 0xAC, 0xBC], // LDY
+    &[0x4A, 0x46, 0x56, 0x4E, 0x5E], // LSR
+    &[0xEA], // NOP
+    &[0x09, 0x05, 0x15, 0x0D, 0x1D, 0x19, 0x01, 0x11], // ORA
+    &[0x48], &[0x08], &[0x68], &[0x28], // PHA PHP PLA PLP
+    &[0x2A, 0x26, 0x36, 0x2E, 0x3E], // ROL
+    &[0x6A, 0x66, 0x76, 0x6E, 0x7E], // ROR
+    &[0x40], &[0x60], // RTI RTS
+    &[0xE9, 0xE5, 0xF5, 0xED, 0xFD, 0xF9, 0xE1, 0xF1], // SBC
+    &[0x38], &[0xF8], &[0x78], // SEC SED SEI
+    &[0x85, 0x95, 0x8D, 0x9D, 0x99, 0x81, 0x91], // STA
+    &[0x86, 0x96, 0x8E], // STX
+    &[0x84, 0x94, 0x8C], // STY
+    &[0xAA], &[0xA8], &[0xBA], &[0x8A], &[0x9A], &[0x98], // TAX TAY TSX TXA TXS TYA
+];
+
+#[test]
+fn test_dispatch_table_official_opcodes() {
+    use crate::cpu::DISPATCH_TABLE;
+
+    let official: Vec<u8> = OFFICIAL_OPCODES.concat();
+    assert_eq!(official.len(), 151);
+
+    for value in opcodes::VALUES {
+        let count = DISPATCH_TABLE.iter().filter(|byte| *byte == value).count();
+        assert_eq!(count, 1, "opcode {:#04X} dispatched {} times", value, count);
+    }
+
+    let mut dispatched = DISPATCH_TABLE.to_vec();
+    dispatched.sort();
+    let mut expected = official.clone();
+    expected.sort();
+    assert_eq!(dispatched, expected);
+
+    // Step each one on a zeroed CPU, operands of 0 cross no pages and
+    // branch to the next instruction either way
+    for byte_code in official {
+        let mut cpu: CPU = CPU::new();
+        cpu.load(vec![byte_code]);
+        cpu.reset_interrupt();
+
+        let opcode = opcodes::lookup(byte_code).unwrap();
+        let step = cpu.step().unwrap();
+        assert_eq!(step.opcode, byte_code);
+        assert_eq!(
+            step.cycles, opcode.cycles as u64 + step.branch_taken as u64,
+            "{} {:#04X} cycles", opcode.name, byte_code);
+
+        match opcode.name {
+            "BRK" | "JMP" | "JSR" | "RTI" | "RTS" => {}
+            _ => assert_eq!(
+                cpu.counter, step.addr + opcode.len as u16,
+                "{} {:#04X} counter", opcode.name, byte_code),
+        }
+    }
+}
+
+#[test]
+fn test_dispatch_table_illegal_opcodes() {
+    use crate::cpu::{DISPATCH_TABLE, ILLEGAL_DISPATCH_TABLE};
+
+    for value in opcodes::illegal::VALUES {
+        let count = ILLEGAL_DISPATCH_TABLE.iter().filter(|byte| *byte == value).count();
+        assert_eq!(count, 1, "opcode {:#04X} dispatched {} times", value, count);
+        assert!(!DISPATCH_TABLE.contains(value));
+    }
+    assert_eq!(ILLEGAL_DISPATCH_TABLE.len(), opcodes::illegal::VALUES.len());
+}
+
+#[test]
+fn test_illegal_opcodes() {
+    use crate::opcodes::illegal::{LAX, DCP, ISB, SLO, RRA, SAX};
+
+    let mut cpu: CPU = CPU::new();
+    cpu.halt_on_brk = true;
+    let program = vec![
+        LAX::ZERO_PAGE::VALUE, 0x10,  // A = X = 0x81
+        SAX::ZERO_PAGE::VALUE, 0x11,  // 0x11 = 0x81
+        DCP::ZERO_PAGE::VALUE, 0x12,  // 0x12 = 0x81, equal to A
+        SLO::ZERO_PAGE::VALUE, 0x13,  // 0x13 = 0x02, A = 0x83
+        ISB::ZERO_PAGE::VALUE, 0x14,  // 0x14 = 0x03, A = 0x83 - 0x03 - 1, C = 1
+        RRA::ZERO_PAGE::VALUE, 0x15,  // 0x15 = 0x82, A = 0x7F + 0x82
+        0x1A,                         // NOP implied
+        0x80, 0xFF,                   // NOP immediate
+        0x0C, 0x00, 0x90,             // NOP absolute
+        BRK::NONE_ADDRESSING::VALUE
+    ];
+
+    cpu.load(program);
+    cpu.reset_interrupt();
+    cpu.mem_write(0x10, 0x81);
+    cpu.mem_write(0x12, 0x82);
+    cpu.mem_write(0x13, 0x01);
+    cpu.mem_write(0x14, 0x02);
+    cpu.mem_write(0x15, 0x04);
+    cpu.run().unwrap();
+
+    assert_eq!(cpu.register_x, 0x81);
+    assert_eq!(cpu.mem_read(0x11), 0x81);
+    assert_eq!(cpu.mem_read(0x12), 0x81);
+    assert_eq!(cpu.mem_read(0x13), 0x02);
+    assert_eq!(cpu.mem_read(0x14), 0x03);
+    assert_eq!(cpu.mem_read(0x15), 0x82);
+    assert_eq!(cpu.register_a, 0x01);
+    assert_eq!(cpu.counter, 0x8013);
+}
+
+#[test]
+fn test_illegal_opcodes_trap_when_disabled() {
+    use crate::cpu::CpuError;
+    use crate::opcodes::illegal::LAX;
+
+    let mut cpu: CPU = CPU::new();
+    cpu.halt_on_brk = true;
+    cpu.illegal_opcodes = false;
+
+    let result = cpu.load_and_run(vec![LAX::ZERO_PAGE::VALUE, 0x10, BRK::NONE_ADDRESSING::VALUE]);
+
+    assert_eq!(result, Err(CpuError::IllegalOpcode { opcode: 0xA7, addr: 0x8000 }));
+    assert_eq!(cpu.counter, 0x8000);
+
+    // An NMI serviced on the way in is still caught up with, the trap
+    // itself takes no time
+    let cycles = cpu.cycles;
+    let dot = cpu.bus.ppu.dot;
+    cpu.mem_write_u16(0xFFFA, 0x8000);
+    cpu.trigger_nmi();
+    assert!(cpu.step().is_err());
+    assert_eq!(cpu.cycles, cycles + 7);
+    assert_eq!(cpu.bus.ppu.dot, dot + 21);
+}
+
+#[test]
+fn test_jam_and_invalid_addressing_mode() {
+    use crate::cpu::CpuError;
+    use crate::opcodes::illegal::JAM;
+    use crate::opcodes::{AddressingMode, NOP};
+
+    let mut cpu: CPU = CPU::new();
+    cpu.load(vec![NOP::NONE_ADDRESSING::VALUE, JAM::NONE_ADDRESSING_02::VALUE]);
+    cpu.reset_interrupt();
+
+    assert!(cpu.step().is_ok());
+    // Stays jammed on the same byte however many times it's stepped
+    assert_eq!(cpu.step(), Err(CpuError::Jammed { opcode: 0x02, addr: 0x8001 }));
+    assert_eq!(cpu.step(), Err(CpuError::Jammed { opcode: 0x02, addr: 0x8001 }));
+    assert_eq!(cpu.counter, 0x8001);
+
+    // JMP only has absolute and indirect forms
+    cpu.counter = 0x8001;
+    cpu.counter_state = 0x8002;
+    cpu.jump(AddressingMode::ZERO_PAGE);
+    assert_eq!(
+        cpu.take_fault(),
+        Some(CpuError::InvalidAddressingMode { mode: AddressingMode::ZERO_PAGE, addr: 0x8001 }));
+}
+
+#[test]
+fn test_fault_outside_step_does_not_leak() {
+    use crate::opcodes::{AddressingMode, NOP};
+
+    let mut cpu: CPU = CPU::new();
+    cpu.load(vec![NOP::NONE_ADDRESSING::VALUE]);
+    cpu.reset_interrupt();
+
+    // A store without an address to go to, called directly
+    STA::execute(&mut cpu, AddressingMode::NONE_ADDRESSING);
+
+    let step = cpu.step().unwrap();
+    assert_eq!(step.opcode, NOP::NONE_ADDRESSING::VALUE);
+    assert_eq!(cpu.counter, 0x8001);
+}
+
+#[test]
+fn test_faulting_instruction_is_undone() {
+    use crate::cpu::CpuError;
+
+    let mut cpu: CPU = CPU::new();
+    cpu.load(vec![STA::ABSOLUTE::VALUE, 0x00, 0x02]);
+    cpu.reset_interrupt();
+    cpu.register_a = 0x22;
+    let cycles = cpu.cycles;
+    let dot = cpu.bus.ppu.dot;
+
+    // Stores, changes a register and faults half way through
+    let err = CpuError::Jammed { opcode: STA::ABSOLUTE::VALUE, addr: 0x8000 };
+    let result = cpu.step_with(|cpu, _| {
+        STA::ABSOLUTE::execute(cpu);
+        cpu.register_a = 0x33;
+        cpu.raise(err);
+        cpu.mem_write(0x0201, 0x44);
+        return true;
+    });
+
+    assert_eq!(result, Err(err));
+    assert_eq!(cpu.register_a, 0x22);
+    assert_eq!(cpu.counter, 0x8000);
+    assert_eq!(cpu.cycles, cycles);
+    assert_eq!(cpu.bus.ppu.dot, dot);
+    // Only what happened before the fault made it to the bus
+    assert_eq!(cpu.mem_peek(0x0200), 0x22);
+    assert_eq!(cpu.mem_peek(0x0201), 0x00);
+}
+
+#[test]
+fn test_cycles_per_frame_with_nmi() {
+    use crate::opcodes::{JMP, RTI};
+
+    let mut cpu: CPU = CPU::new();
+    cpu.load(vec![
+        LDA::IMMEDIATE::VALUE, 0x80,
+        STA::ABSOLUTE::VALUE, 0x00, 0x20, // PPUCTRL, NMI on VBlank
+        JMP::ABSOLUTE::VALUE, 0x05, 0x80, // Spin
+        RTI::NONE_ADDRESSING::VALUE,
+    ]);
+    cpu.mem_write_u16(0xFFFA, 0x8008);
+    cpu.reset_interrupt();
+    cpu.halt_on_brk = true;
+
+    // Cycles at the first instruction after VBlank of frames 1 and 61
+    let mut frames = 0;
+    let mut marks = vec![];
+    cpu.run_with_callback(|cpu| {
+        if cpu.bus.ppu.frames == frames {
+            return;
+        }
+        frames = cpu.bus.ppu.frames;
+        if frames == 1 || frames == 61 {
+            marks.push(cpu.cycles);
+        }
+        if frames == 61 {
+            cpu.mem_write(0x0200, BRK::NONE_ADDRESSING::VALUE);
+            cpu.counter = 0x0200;
+        }
+    }).unwrap();
+
+    // 341 dots on 262 lines at 3 dots a cycle, NMIs included
+    let expected = 60 * 341 * 262 / 3;
+    let cycles = marks[1] - marks[0];
+    assert!(cycles.abs_diff(expected) < 10, "{} cycles, expected {}", cycles, expected);
+}
+
+#[test]
+fn test_ppu_vblank_nmi() {
+    use crate::opcodes::{JMP, RTI};
+
+    let mut cpu: CPU = CPU::new();
+    cpu.load(vec![
+        LDA::IMMEDIATE::VALUE, 0x80,
+        STA::ABSOLUTE::VALUE, 0x00, 0x20, // PPUCTRL, NMI on VBlank
+        JMP::ABSOLUTE::VALUE, 0x05, 0x80, // Spin
+    ]);
+    // NMI handler at 0x9000 counts frames
+    cpu.mem_write_u16(0xFFFA, 0x9000);
+    cpu.mem_write(0x9000, INX::NONE_ADDRESSING::VALUE);
+    cpu.mem_write(0x9001, RTI::NONE_ADDRESSING::VALUE);
+    cpu.reset_interrupt();
+
+    while cpu.register_x == 0 {
+        cpu.step().unwrap();
+    }
+
+    // VBlank starts at dot 1 of scanline 241
+    let vblank = (241 * 341 + 1) / 3;
+    assert!(cpu.cycles >= vblank && cpu.cycles < vblank + 20);
+    assert_eq!(cpu.bus.ppu.scanline, 241);
+
+    while cpu.register_x == 1 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.bus.ppu.frames, 2);
+}
+
+#[test]
+fn test_oam_dma() {
+    let mut cpu: CPU = CPU::new();
+    cpu.load(vec![
+        LDA::IMMEDIATE::VALUE, 0x10,
+        STA::ABSOLUTE::VALUE, 0x03, 0x20, // OAMADDR
+        LDA::IMMEDIATE::VALUE, 0x02,
+        STA::ABSOLUTE::VALUE, 0x14, 0x40, // OAMDMA from 0x0200
+        STA::ABSOLUTE::VALUE, 0x14, 0x40,
+    ]);
+    cpu.reset_interrupt();
+    for i in 0..256 {
+        cpu.mem_write(0x0200 + i, i as u8);
+    }
+
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+
+    // Copy starts at OAMADDR and wraps around
+    let step = cpu.step().unwrap();
+    assert_eq!(cpu.bus.ppu.oam[0x10], 0x00);
+    assert_eq!(cpu.bus.ppu.oam[0xFF], 0xEF);
+    assert_eq!(cpu.bus.ppu.oam[0x00], 0xF0);
+
+    // Finished on cycle 12, even, then on an odd one
+    assert_eq!(step.cycles, 4 + 513);
+    assert_eq!(cpu.step().unwrap().cycles, 4 + 514);
+}
+
+#[test]
+fn test_dmc_fetch_stalls_cpu() {
+    let mut cpu: CPU = CPU::new();
+    cpu.load(vec![
+        LDA::IMMEDIATE::VALUE, 0x00,
+        STA::ABSOLUTE::VALUE, 0x12, 0x40, // Sample at 0xC000
+        STA::ABSOLUTE::VALUE, 0x13, 0x40, // 1 byte long
+        LDA::IMMEDIATE::VALUE, 0x10,
+        STA::ABSOLUTE::VALUE, 0x15, 0x40, // Enable DMC
+        LDA::IMMEDIATE::VALUE, 0x00,
+    ]);
+    cpu.reset_interrupt();
+    cpu.mem_write(0xC000, 0xAA);
+
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+
+    // The sample byte is read through the bus straight after enabling
+    let step = cpu.step().unwrap();
+    assert_eq!(step.cycles, 4 + 4);
+    assert_eq!(cpu.bus.apu.dmc.bytes_remaining, 0);
+    assert_eq!(cpu.bus.apu.dmc.sample_request(), None);
+
+    assert_eq!(cpu.step().unwrap().cycles, 2);
+}
+
+#[test]
+fn test_mmc3_scanline_irq() {
+    use crate::cartridge::RomBuilder;
+    use crate::opcodes::{CLI, JMP, RTI};
+
+    // 32 KiB of PRG in 8 KiB banks, the last one fixed at 0xE000
+    let mut prg_rom = vec![0; 0x8000];
+    let program = [
+        LDA::IMMEDIATE::VALUE, 0x00,
+        STA::ABSOLUTE::VALUE, 0x00, 0xC0, // IRQ latch 0, every scanline
+        STA::ABSOLUTE::VALUE, 0x01, 0xC0, // Reload
+        STA::ABSOLUTE::VALUE, 0x01, 0xE0, // Enable
+        LDA::IMMEDIATE::VALUE, 0x08,
+        STA::ABSOLUTE::VALUE, 0x00, 0x20, // Sprites from 0x1000
+        LDA::IMMEDIATE::VALUE, 0x18,
+        STA::ABSOLUTE::VALUE, 0x01, 0x20, // Show background and sprites
+        LDA::IMMEDIATE::VALUE, 0x40,
+        STA::ABSOLUTE::VALUE, 0x17, 0x40, // No APU frame IRQ
+        CLI::NONE_ADDRESSING::VALUE,
+        JMP::ABSOLUTE::VALUE, 0x1B, 0xE0,
+    ];
+    // Acknowledge, enable again and count
+    let handler = [
+        STA::ABSOLUTE::VALUE, 0x00, 0xE0,
+        STA::ABSOLUTE::VALUE, 0x01, 0xE0,
+        INX::NONE_ADDRESSING::VALUE,
+        RTI::NONE_ADDRESSING::VALUE,
+    ];
+    let last_bank = 0x6000;
+    prg_rom[last_bank..last_bank + program.len()].copy_from_slice(&program);
+    prg_rom[last_bank + 0x100..last_bank + 0x100 + handler.len()].copy_from_slice(&handler);
+    prg_rom[last_bank + 0x1FFC..last_bank + 0x2000].copy_from_slice(&[0x00, 0xE0, 0x00, 0xE1]);
+
+    let mut cpu: CPU = CPU::new();
+    cpu.load_rom(RomBuilder::new(4).prg_rom(prg_rom).chr_rom(vec![0; 0x2000]).build()).unwrap();
+    cpu.reset_interrupt();
+
+    while cpu.bus.ppu.frames < 1 {
+        cpu.step().unwrap();
+    }
+    let start = cpu.register_x;
+    while cpu.bus.ppu.frames < 2 {
+        cpu.step().unwrap();
+    }
+
+    // An IRQ per rendered scanline
+    assert_eq!(cpu.register_x.wrapping_sub(start), 241);
+}
+
+#[test]
+fn test_mmc1_read_modify_write_reset() {
+    use crate::cartridge::{numbered_banks, RomBuilder};
+    use crate::opcodes::INC;
+
+    // 8 PRG banks holding their number, bank 0 holds 0xFF so INC wraps it
+    // to 0 and only the dummy write has bit 7 set
+    let mut prg_rom = numbered_banks(8, 0x4000);
+    prg_rom[..0x4000].fill(0xFF);
+    let program = [
+        LDA::IMMEDIATE::VALUE, 0x01,
+        STA::ABSOLUTE::VALUE, 0x00, 0xE0,
+        STA::ABSOLUTE::VALUE, 0x00, 0xE0, // Half loaded
+        INC::ABSOLUTE::VALUE, 0x00, 0x80, // Resets, the write of 0 is ignored
+        LDA::IMMEDIATE::VALUE, 0x00,
+        STA::ABSOLUTE::VALUE, 0x00, 0xE0,
+        LDA::IMMEDIATE::VALUE, 0x01,
+        STA::ABSOLUTE::VALUE, 0x00, 0xE0,
+        LDA::IMMEDIATE::VALUE, 0x00,
+        STA::ABSOLUTE::VALUE, 0x00, 0xE0,
+        STA::ABSOLUTE::VALUE, 0x00, 0xE0,
+        STA::ABSOLUTE::VALUE, 0x00, 0xE0, // PRG bank 2
+        LDA::ABSOLUTE::VALUE, 0x00, 0x80,
+    ];
+    let last_bank = 7 * 0x4000;
+    prg_rom[last_bank..last_bank + program.len()].copy_from_slice(&program);
+    prg_rom[last_bank + 0x3FFC..last_bank + 0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+
+    let mut cpu: CPU = CPU::new();
+    cpu.load_rom(RomBuilder::new(1).prg_rom(prg_rom).build()).unwrap();
+    cpu.reset_interrupt();
+
+    for _ in 0..13 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.register_a, 2);
+}
+
+#[test]
+fn test_gamepad_ports() {
+    use crate::gamepad::Buttons;
+    use crate::opcodes::LDY;
+
+    let mut cpu: CPU = CPU::new();
+    cpu.load(vec![
+        LDA::IMMEDIATE::VALUE, 0x01,
+        STA::ABSOLUTE::VALUE, 0x16, 0x40, // Strobe both ports
+        LDA::IMMEDIATE::VALUE, 0x00,
+        STA::ABSOLUTE::VALUE, 0x16, 0x40,
+        LDA::ABSOLUTE::VALUE, 0x16, 0x40, // Port 1 A
+        LDX::ABSOLUTE::VALUE, 0x17, 0x40, // Port 2 A
+        LDY::ABSOLUTE::VALUE, 0x17, 0x40, // Port 2 B
+    ]);
+    cpu.reset_interrupt();
+    cpu.bus.gamepads[0].buttons = Buttons::A;
+    cpu.bus.gamepads[1].buttons = Buttons::B;
+
+    for _ in 0..7 {
+        cpu.step().unwrap();
+    }
+
+    assert_eq!(cpu.register_a, 0x41);
+    assert_eq!(cpu.register_x, 0x40);
+    assert_eq!(cpu.register_y, 0x41);
+
+    // The undriven bits are whatever was last on the data bus
+    cpu.bus.mem_write(0x0000, 0xFF);
+    assert_eq!(cpu.bus.mem_read(gamepad::JOYPAD2), 0xE0);
+}
+
+#[test]
+fn test_step() {
+    use crate::cpu::Step;
+    use crate::opcodes::AddressingMode;
+
+    let mut cpu: CPU = CPU::new();
+    cpu.load(vec![
+        LDX::IMMEDIATE::VALUE, 0x01,
+        LDA::ABSOLUTE_X::VALUE, 0xFF, 0x02,
+        BNE::NONE_ADDRESSING::VALUE, 0x00,
+    ]);
+    cpu.reset_interrupt();
+
+    assert_eq!(cpu.step(), Ok(Step {
+        addr: 0x8000,
+        opcode: LDX::IMMEDIATE::VALUE,
+        mode: AddressingMode::IMMEDIATE,
+        operand_addr: Some(0x8001),
+        cycles: 2,
+        branch_taken: false,
+    }));
+    assert_eq!(cpu.step(), Ok(Step {
+        addr: 0x8002,
+        opcode: LDA::ABSOLUTE_X::VALUE,
+        mode: AddressingMode::ABSOLUTE_X,
+        operand_addr: Some(0x0300),
+        cycles: 5,
+        branch_taken: false,
+    }));
+
+    let step = cpu.step().unwrap();
+    assert_eq!(step.operand_addr, None);
+    assert_eq!(step.cycles, 2);
+    assert!(!step.branch_taken);
+
+    cpu.register_x = 1;
+    cpu.counter = 0x8005;
+    cpu.status.remove(Flag::Zero);
+    let step = cpu.step().unwrap();
+    assert_eq!(step.cycles, 3);
+    assert!(step.branch_taken);
+    assert_eq!(cpu.counter, 0x8007);
+}
+
 #[test]
 fn test_match_bit_and() {
 
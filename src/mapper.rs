@@ -0,0 +1,275 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::axrom::Axrom;
+use crate::cartridge::{Mirroring, Rom};
+use crate::cnrom::Cnrom;
+use crate::mmc1::Mmc1;
+use crate::mmc3::Mmc3;
+use crate::uxrom::Uxrom;
+
+// [0x6000 .. 0x7FFF] PRG RAM, battery backed on some boards
+pub const PRG_RAM: u16 = 0x6000;
+pub const PRG_RAM_END: u16 = 0x7FFF;
+// [0x8000 .. 0xFFFF] PRG ROM
+pub const PRG_ROM: u16 = 0x8000;
+
+pub const PRG_RAM_SIZE: usize = 8 * 1024;
+// Boards without CHR ROM have 8 KiB of CHR RAM in its place
+pub const CHR_RAM_SIZE: usize = 8 * 1024;
+
+const CARTRIDGE_SPACE: u16 = 0x4020;
+
+/// Board logic of a cartridge. The CPU sees it through cartridge space
+/// [0x4020 .. 0xFFFF] and the PPU through the pattern tables
+/// [0x0000 .. 0x1FFF] and the nametable mirroring it selects.
+pub trait Mapper {
+    fn cpu_peek(&self, addr: u16) -> u8;
+
+    // Reads with side effects, most boards have none
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        return self.cpu_peek(addr);
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    fn ppu_read(&self, addr: u16) -> u8;
+
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    // Level of the board's IRQ output
+    fn irq(&self) -> bool {
+        return false;
+    }
+
+    // Called with the CPU cycle a write to cartridge space lands on, just
+    // before the write
+    fn cpu_cycle(&mut self, _cycle: u64) {}
+
+    // Called by the PPU when its address line A12 rises after being low
+    // for a while, once per scanline while rendering with backgrounds and
+    // sprites in different pattern tables
+    fn a12_rise(&mut self) {}
+
+    // PRG RAM kept by a battery while the console is off, None on boards
+    // without one
+    fn battery_ram(&self) -> Option<&[u8]> {
+        return None;
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return None;
+    }
+}
+
+// The bus and the PPU both hold on to the cartridge
+pub type SharedMapper = Rc<RefCell<dyn Mapper>>;
+
+pub fn shared<M: Mapper + 'static>(mapper: M) -> SharedMapper {
+    return Rc::new(RefCell::new(mapper));
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MapperError {
+    Unsupported(u16),
+}
+
+impl fmt::Display for MapperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapperError::Unsupported(mapper) => write!(f, "mapper {} is not supported", mapper),
+        }
+    }
+}
+
+impl std::error::Error for MapperError {}
+
+// Picks the board for the mapper number in the header
+pub fn from_rom(rom: Rom) -> Result<SharedMapper, MapperError> {
+    return match rom.mapper {
+        0 => Ok(shared(Nrom::new(rom.prg_rom, rom.chr_rom, rom.mirroring))),
+        1 => Ok(shared(Mmc1::new(rom))),
+        2 => Ok(shared(Uxrom::new(rom))),
+        3 => Ok(shared(Cnrom::new(rom))),
+        4 => Ok(shared(Mmc3::new(rom))),
+        7 => Ok(shared(Axrom::new(rom))),
+        mapper => Err(MapperError::Unsupported(mapper)),
+    };
+}
+
+// CHR ROM, or CHR RAM when the cartridge has none. The flag is set for RAM.
+pub fn chr_memory(chr_rom: Vec<u8>) -> (Vec<u8>, bool) {
+    return match chr_rom.is_empty() {
+        true => (vec![0; CHR_RAM_SIZE], true),
+        false => (chr_rom, false),
+    };
+}
+
+// NES 2.0 submappers 1 and 2 of discrete boards say whether they have bus
+// conflicts, otherwise it is down to what the common boards do
+pub fn has_bus_conflicts(rom: &Rom, default: bool) -> bool {
+    return match rom.submapper {
+        1 => false,
+        2 => true,
+        _ => default,
+    };
+}
+
+// Discrete boards without a chip enable on the ROM read it while latching
+// a write, the value seen is the written one ANDed with the ROM byte
+pub fn bus_conflict(data: u8, rom: u8, bus_conflicts: bool) -> u8 {
+    return match bus_conflicts {
+        true => data & rom,
+        false => data,
+    };
+}
+
+/// Mapper 0, no bank switching. 16 KiB of PRG ROM is mirrored to fill
+/// all 32 KiB.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    pub mirroring: Mirroring,
+}
+
+impl Nrom {
+
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        let (chr, chr_ram) = chr_memory(chr_rom);
+
+        Nrom {
+            prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr,
+            chr_ram,
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        return match addr {
+            PRG_RAM ..= PRG_RAM_END => self.prg_ram[(addr - PRG_RAM) as usize],
+            PRG_ROM ..= 0xFFFF if !self.prg_rom.is_empty() => {
+                self.prg_rom[(addr - PRG_ROM) as usize % self.prg_rom.len()]
+            }
+            _ => 0,
+        };
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let PRG_RAM ..= PRG_RAM_END = addr {
+            self.prg_ram[(addr - PRG_RAM) as usize] = data;
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        return self.chr.get(addr as usize).copied().unwrap_or(0);
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        // CHR ROM can't be written
+        if self.chr_ram {
+            if let Some(byte) = self.chr.get_mut(addr as usize) {
+                *byte = data;
+            }
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+}
+
+/// Stands in for a cartridge when running raw programs, all of cartridge
+/// space is RAM so they can be loaded at 0x8000.
+pub struct RamCartridge {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+}
+
+impl RamCartridge {
+
+    pub fn new() -> Self {
+        RamCartridge {
+            prg: vec![0; (0xFFFF - CARTRIDGE_SPACE) as usize + 1],
+            chr: vec![0; CHR_RAM_SIZE],
+        }
+    }
+}
+
+impl Mapper for RamCartridge {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        return self.prg[(addr - CARTRIDGE_SPACE) as usize];
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        self.prg[(addr - CARTRIDGE_SPACE) as usize] = data;
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        return self.chr[addr as usize % CHR_RAM_SIZE];
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr[addr as usize % CHR_RAM_SIZE] = data;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return Mirroring::Horizontal;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::RomBuilder;
+
+    #[test]
+    fn test_nrom_prg_mirroring() {
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[0x0000] = 0x11;
+        prg_rom[0x3FFF] = 0x22;
+        let nrom = Nrom::new(prg_rom, vec![], Mirroring::Vertical);
+
+        assert_eq!(nrom.cpu_peek(0x8000), 0x11);
+        assert_eq!(nrom.cpu_peek(0xBFFF), 0x22);
+        assert_eq!(nrom.cpu_peek(0xC000), 0x11);
+        assert_eq!(nrom.cpu_peek(0xFFFF), 0x22);
+    }
+
+    #[test]
+    fn test_nrom_32k_prg() {
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[0x4000] = 0x33;
+        let nrom = Nrom::new(prg_rom, vec![], Mirroring::Vertical);
+
+        assert_eq!(nrom.cpu_peek(0xC000), 0x33);
+        assert_eq!(nrom.cpu_peek(0x8000), 0x00);
+    }
+
+    #[test]
+    fn test_nrom_writes() {
+        let mut nrom = Nrom::new(vec![0xEA; 0x4000], vec![0xAA; 0x2000], Mirroring::Horizontal);
+
+        // PRG RAM is writable, PRG and CHR ROM are not
+        nrom.cpu_write(0x6000, 0x01);
+        nrom.cpu_write(0x8000, 0x02);
+        nrom.ppu_write(0x0000, 0x03);
+        assert_eq!(nrom.cpu_peek(0x6000), 0x01);
+        assert_eq!(nrom.cpu_peek(0x8000), 0xEA);
+        assert_eq!(nrom.ppu_read(0x0000), 0xAA);
+        assert_eq!(nrom.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        let rom = RomBuilder::new(0x0F).prg_rom(vec![0; 0x4000]).build();
+
+        assert_eq!(from_rom(rom).err(), Some(MapperError::Unsupported(0x0F)));
+    }
+}
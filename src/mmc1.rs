@@ -0,0 +1,363 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{chr_memory, Mapper, PRG_RAM, PRG_RAM_END, PRG_RAM_SIZE, PRG_ROM};
+
+const PRG_BANK_SIZE: usize = 16 * 1024;
+const CHR_BANK_SIZE: usize = 4 * 1024;
+// SUROM and friends select 256 KiB halves of their PRG with CHR bank bit 4
+const PRG_OUTER_BANK_SIZE: usize = 256 * 1024;
+
+// Shift register with only the marker bit, set on reset and after the
+// fifth write
+const SHIFT_RESET: u8 = 0b1_0000;
+// Control on power up, PRG mode 3 so the reset vector is in the last bank
+const CONTROL_RESET: u8 = 0b0_1100;
+
+/// Mapper 1, Nintendo's MMC1. Registers are loaded a bit at a time
+/// through a serial port covering all of [0x8000 .. 0xFFFF], the address
+/// of the fifth write picks the register.
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    // Battery backed on boards like SNROM
+    prg_ram: Vec<u8>,
+    battery: bool,
+    chr: Vec<u8>,
+    chr_ram: bool,
+
+    shift: u8,
+    // CPPMM: CHR mode, PRG mode, mirroring
+    pub control: u8,
+    pub chr_bank_0: u8,
+    pub chr_bank_1: u8,
+    // RPPPP: PRG RAM disable, PRG bank
+    pub prg_bank: u8,
+
+    // CPU cycle of the write being made, and of the last one the serial
+    // port saw
+    cycle: u64,
+    last_write: Option<u64>,
+}
+
+impl Mmc1 {
+
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_memory(rom.chr_rom);
+
+        Mmc1 {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; rom.prg_ram_size.max(PRG_RAM_SIZE)],
+            battery: rom.battery,
+            chr,
+            chr_ram,
+            shift: SHIFT_RESET,
+            control: CONTROL_RESET,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write: None,
+        }
+    }
+
+    fn write_serial(&mut self, addr: u16, data: u8) {
+        // The MMC1 only looks at the first of writes on consecutive cycles,
+        // e.g. the dummy write of a read-modify-write instruction
+        let consecutive = self.last_write.map_or(false, |last| self.cycle <= last + 1);
+        self.last_write = Some(self.cycle);
+        if consecutive {
+            return;
+        }
+
+        if data & 0b1000_0000 != 0 {
+            self.shift = SHIFT_RESET;
+            self.control |= CONTROL_RESET;
+            return;
+        }
+
+        // The marker reaching bit 0 means this is the fifth write
+        let complete = self.shift & 1 != 0;
+        self.shift = (self.shift >> 1) | ((data & 1) << 4);
+        if !complete {
+            return;
+        }
+
+        let value = self.shift;
+        self.shift = SHIFT_RESET;
+        match addr {
+            0x8000 ..= 0x9FFF => self.control = value,
+            0xA000 ..= 0xBFFF => self.chr_bank_0 = value,
+            0xC000 ..= 0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        return self.prg_bank & 0b1_0000 == 0;
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank_count = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let offset = (addr - PRG_ROM) as usize % PRG_BANK_SIZE;
+        let high = addr >= 0xC000;
+
+        // Large boards pick the outer 256 KiB first, the modes below only
+        // bank within it
+        let outer = match self.prg_rom.len() > PRG_OUTER_BANK_SIZE {
+            true => ((self.chr_bank_0 >> 4) & 1) as usize * (PRG_OUTER_BANK_SIZE / PRG_BANK_SIZE),
+            false => 0,
+        };
+        let inner_count = bank_count.min(PRG_OUTER_BANK_SIZE / PRG_BANK_SIZE);
+        let bank = (self.prg_bank & 0b1111) as usize;
+
+        let bank = match ((self.control >> 2) & 0b11, high) {
+            // 32 KiB at a time, low bit ignored
+            (0 | 1, false) => bank & !1,
+            (0 | 1, true) => bank | 1,
+            // First bank fixed at 0x8000
+            (2, false) => 0,
+            (2, true) => bank,
+            // Last bank fixed at 0xC000
+            (_, false) => bank,
+            (_, true) => inner_count - 1,
+        };
+
+        return ((outer + bank % inner_count) % bank_count) * PRG_BANK_SIZE + offset;
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let offset = addr as usize % CHR_BANK_SIZE;
+        let high = addr as usize >= CHR_BANK_SIZE;
+
+        let bank = match (self.control & 0b1_0000 != 0, high) {
+            // Two 4 KiB banks
+            (true, false) => self.chr_bank_0,
+            (true, true) => self.chr_bank_1,
+            // 8 KiB at a time, low bit ignored
+            (false, false) => self.chr_bank_0 & !1,
+            (false, true) => self.chr_bank_0 | 1,
+        };
+
+        return (bank as usize * CHR_BANK_SIZE + offset) % self.chr.len();
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        return match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_enabled() => {
+                self.prg_ram[(addr - PRG_RAM) as usize % self.prg_ram.len()]
+            }
+            PRG_ROM ..= 0xFFFF if !self.prg_rom.is_empty() => {
+                self.prg_rom[self.prg_addr(addr) % self.prg_rom.len()]
+            }
+            _ => 0,
+        };
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_enabled() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - PRG_RAM) as usize % len] = data;
+            }
+            PRG_ROM ..= 0xFFFF => self.write_serial(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        return self.chr[self.chr_addr(addr)];
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let index = self.chr_addr(addr);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        };
+    }
+
+    fn cpu_cycle(&mut self, cycle: u64) {
+        self.cycle = cycle;
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        return match self.battery {
+            true => Some(&self.prg_ram),
+            false => None,
+        };
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return match self.battery {
+            true => Some(&mut self.prg_ram),
+            false => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{numbered_banks, RomBuilder};
+
+    // PRG banks start with their number, CHR 4 KiB banks too
+    fn mmc1(prg_banks: usize, chr_banks: usize) -> Mmc1 {
+        return Mmc1::new(RomBuilder::new(1)
+            .prg_rom(numbered_banks(prg_banks, PRG_BANK_SIZE))
+            .chr_rom(numbered_banks(chr_banks, CHR_BANK_SIZE))
+            .build());
+    }
+
+    // Serial load of the low 5 bits, a write per instruction
+    fn write_register(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(addr, (value >> bit) & 1);
+            mmc1.cpu_cycle(mmc1.cycle + 4);
+        }
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank() {
+        let mmc1 = mmc1(8, 2);
+        assert_eq!(mmc1.cpu_peek(0x8000), 0);
+        assert_eq!(mmc1.cpu_peek(0xC000), 7);
+    }
+
+    #[test]
+    fn test_empty_prg_rom() {
+        let mut rom = RomBuilder::new(1).build();
+        rom.prg_rom.clear();
+        let mmc1 = Mmc1::new(rom);
+        assert_eq!(mmc1.cpu_peek(0xFFFC), 0);
+    }
+
+    #[test]
+    fn test_serial_load() {
+        let mut mmc1 = mmc1(8, 2);
+        write_register(&mut mmc1, 0xE000, 0b1_0101);
+        assert_eq!(mmc1.prg_bank, 0b1_0101);
+
+        // 4 writes don't complete a load
+        for _ in 0..4 {
+            mmc1.cpu_write(0xE000, 1);
+            mmc1.cpu_cycle(mmc1.cycle + 4);
+        }
+        assert_eq!(mmc1.prg_bank, 0b1_0101);
+    }
+
+    #[test]
+    fn test_reset_on_bit_7() {
+        let mut mmc1 = mmc1(8, 2);
+        write_register(&mut mmc1, 0x8000, 0b0_0000);
+
+        // Half loaded, then reset
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_cycle(mmc1.cycle + 4);
+        mmc1.cpu_write(0xE000, 0x80);
+        mmc1.cpu_cycle(mmc1.cycle + 4);
+        assert_eq!(mmc1.control, CONTROL_RESET);
+
+        write_register(&mut mmc1, 0xE000, 0b0_0010);
+        assert_eq!(mmc1.prg_bank, 0b0_0010);
+    }
+
+    #[test]
+    fn test_consecutive_writes_ignored() {
+        let mut mmc1 = mmc1(8, 2);
+        // The second of a back to back pair is dropped
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_cycle(mmc1.cycle + 1);
+        mmc1.cpu_write(0xE000, 0);
+        mmc1.cpu_cycle(mmc1.cycle + 4);
+        for _ in 0..4 {
+            mmc1.cpu_write(0xE000, 0);
+            mmc1.cpu_cycle(mmc1.cycle + 4);
+        }
+        assert_eq!(mmc1.prg_bank, 0b0_0001);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc1 = mmc1(8, 2);
+
+        // Mode 3, switch 0x8000 and fix the last bank
+        write_register(&mut mmc1, 0xE000, 5);
+        assert_eq!(mmc1.cpu_peek(0x8000), 5);
+        assert_eq!(mmc1.cpu_peek(0xC000), 7);
+
+        // Mode 2, fix the first bank and switch 0xC000
+        write_register(&mut mmc1, 0x8000, 0b0_1000);
+        assert_eq!(mmc1.cpu_peek(0x8000), 0);
+        assert_eq!(mmc1.cpu_peek(0xC000), 5);
+
+        // Mode 0, 32 KiB ignoring the low bit
+        write_register(&mut mmc1, 0x8000, 0b0_0000);
+        assert_eq!(mmc1.cpu_peek(0x8000), 4);
+        assert_eq!(mmc1.cpu_peek(0xC000), 5);
+    }
+
+    #[test]
+    fn test_512k_prg_outer_bank() {
+        let mut mmc1 = mmc1(32, 0);
+        assert_eq!(mmc1.cpu_peek(0xC000), 15);
+
+        // CHR bank 0 bit 4 moves to the upper 256 KiB
+        write_register(&mut mmc1, 0xA000, 0b1_0000);
+        write_register(&mut mmc1, 0xE000, 2);
+        assert_eq!(mmc1.cpu_peek(0x8000), 18);
+        assert_eq!(mmc1.cpu_peek(0xC000), 31);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mmc1 = mmc1(2, 8);
+
+        // 8 KiB mode ignores the low bit and CHR bank 1
+        write_register(&mut mmc1, 0xA000, 3);
+        write_register(&mut mmc1, 0xC000, 6);
+        assert_eq!(mmc1.ppu_read(0x0000), 2);
+        assert_eq!(mmc1.ppu_read(0x1000), 3);
+
+        // 4 KiB mode
+        write_register(&mut mmc1, 0x8000, 0b1_1100);
+        assert_eq!(mmc1.ppu_read(0x0000), 3);
+        assert_eq!(mmc1.ppu_read(0x1000), 6);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mmc1 = mmc1(2, 2);
+        for (control, mirroring) in [
+            (0, Mirroring::SingleScreenLower),
+            (1, Mirroring::SingleScreenUpper),
+            (2, Mirroring::Vertical),
+            (3, Mirroring::Horizontal),
+        ] {
+            write_register(&mut mmc1, 0x8000, control);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mmc1 = mmc1(2, 2);
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_peek(0x6000), 0x42);
+
+        // Disabled by PRG bank bit 4
+        write_register(&mut mmc1, 0xE000, 0b1_0000);
+        mmc1.cpu_write(0x6000, 0x11);
+        assert_eq!(mmc1.cpu_peek(0x6000), 0);
+
+        write_register(&mut mmc1, 0xE000, 0b0_0000);
+        assert_eq!(mmc1.cpu_peek(0x6000), 0x42);
+    }
+}
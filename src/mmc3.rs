@@ -0,0 +1,360 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{chr_memory, Mapper, PRG_RAM, PRG_RAM_END, PRG_RAM_SIZE, PRG_ROM};
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+// Bank select, BPxx xRRR
+const BANK_REGISTER: u8 = 0b0000_0111;
+const PRG_MODE: u8 = 0b0100_0000;
+const CHR_INVERSION: u8 = 0b1000_0000;
+
+// PRG RAM protect, EWxx xxxx
+const PRG_RAM_ENABLE: u8 = 0b1000_0000;
+const PRG_RAM_WRITE_PROTECT: u8 = 0b0100_0000;
+
+/// Mapper 4, Nintendo's MMC3. Four 8 KiB PRG windows, eight 1 KiB CHR
+/// windows, and a counter clocked by PPU A12 rises which raises an IRQ
+/// on a chosen scanline.
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    four_screen: bool,
+
+    bank_select: u8,
+    // R0 - R7, R0 and R1 select 2 KiB CHR banks and ignore their low bit
+    pub banks: [u8; 8],
+    pub mirroring: Mirroring,
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    pub irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mmc3 {
+
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_memory(rom.chr_rom);
+
+        Mmc3 {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; rom.prg_ram_size.max(PRG_RAM_SIZE)],
+            battery: rom.battery,
+            chr,
+            chr_ram,
+            four_screen: rom.mirroring == Mirroring::FourScreen,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: rom.mirroring,
+            prg_ram_protect: PRG_RAM_ENABLE,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank_count = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let second_last = bank_count.saturating_sub(2);
+        let window = (addr - PRG_ROM) as usize / PRG_BANK_SIZE;
+        let offset = addr as usize % PRG_BANK_SIZE;
+
+        let r6 = self.banks[6] as usize;
+        let r7 = self.banks[7] as usize;
+        let bank = match (self.bank_select & PRG_MODE != 0, window) {
+            (false, 0) => r6,
+            (true, 0) => second_last,
+            (_, 1) => r7,
+            (false, 2) => second_last,
+            (true, 2) => r6,
+            _ => bank_count - 1,
+        };
+
+        return (bank % bank_count) * PRG_BANK_SIZE + offset;
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        // Inversion swaps the 2 KiB and 1 KiB halves
+        let window = match self.bank_select & CHR_INVERSION != 0 {
+            true => (addr as usize / CHR_BANK_SIZE) ^ 0b100,
+            false => addr as usize / CHR_BANK_SIZE,
+        };
+        let offset = addr as usize % CHR_BANK_SIZE;
+
+        let bank = match window {
+            0 => self.banks[0] & !1,
+            1 => self.banks[0] | 1,
+            2 => self.banks[1] & !1,
+            3 => self.banks[1] | 1,
+            _ => self.banks[window - 2],
+        };
+
+        return (bank as usize * CHR_BANK_SIZE + offset) % self.chr.len();
+    }
+
+    fn prg_ram_readable(&self) -> bool {
+        return self.prg_ram_protect & PRG_RAM_ENABLE != 0;
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        return self.prg_ram_readable() && self.prg_ram_protect & PRG_RAM_WRITE_PROTECT == 0;
+    }
+
+    // Registers are paired, even and odd addresses in each 8 KiB
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let odd = addr & 1 != 0;
+
+        match (addr, odd) {
+            (0x8000 ..= 0x9FFF, false) => self.bank_select = data,
+            (0x8000 ..= 0x9FFF, true) => {
+                self.banks[(self.bank_select & BANK_REGISTER) as usize] = data;
+            }
+            (0xA000 ..= 0xBFFF, false) => {
+                // Boards with four screen VRAM have the mirroring hard wired
+                if !self.four_screen {
+                    self.mirroring = match data & 1 {
+                        0 => Mirroring::Vertical,
+                        _ => Mirroring::Horizontal,
+                    };
+                }
+            }
+            (0xA000 ..= 0xBFFF, true) => self.prg_ram_protect = data,
+            (0xC000 ..= 0xDFFF, false) => self.irq_latch = data,
+            // Reloaded from the latch on the next clock
+            (0xC000 ..= 0xDFFF, true) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            // Disabling also acknowledges a pending IRQ
+            (_, false) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (_, true) => self.irq_enabled = true,
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        return match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_readable() => {
+                self.prg_ram[(addr - PRG_RAM) as usize]
+            }
+            PRG_ROM ..= 0xFFFF if !self.prg_rom.is_empty() => {
+                self.prg_rom[self.prg_addr(addr) % self.prg_rom.len()]
+            }
+            _ => 0,
+        };
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_writable() => {
+                self.prg_ram[(addr - PRG_RAM) as usize] = data;
+            }
+            PRG_ROM ..= 0xFFFF => self.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        return self.chr[self.chr_addr(addr)];
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let index = self.chr_addr(addr);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn irq(&self) -> bool {
+        return self.irq_pending;
+    }
+
+    fn a12_rise(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        return match self.battery {
+            true => Some(&self.prg_ram),
+            false => None,
+        };
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return match self.battery {
+            true => Some(&mut self.prg_ram),
+            false => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{numbered_banks, RomBuilder};
+
+    // 8 KiB PRG banks and 1 KiB CHR banks start with their number
+    fn mmc3(prg_banks: usize, chr_banks: usize) -> Mmc3 {
+        return Mmc3::new(RomBuilder::new(4)
+            .prg_rom(numbered_banks(prg_banks, PRG_BANK_SIZE))
+            .chr_rom(numbered_banks(chr_banks, CHR_BANK_SIZE))
+            .build());
+    }
+
+    fn set_bank(mmc3: &mut Mmc3, register: u8, bank: u8) {
+        let select = (mmc3.bank_select & !BANK_REGISTER) | register;
+        mmc3.cpu_write(0x8000, select);
+        mmc3.cpu_write(0x8001, bank);
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut mmc3 = mmc3(16, 8);
+        set_bank(&mut mmc3, 6, 3);
+        set_bank(&mut mmc3, 7, 5);
+
+        assert_eq!(mmc3.cpu_peek(0x8000), 3);
+        assert_eq!(mmc3.cpu_peek(0xA000), 5);
+        assert_eq!(mmc3.cpu_peek(0xC000), 14);
+        assert_eq!(mmc3.cpu_peek(0xE000), 15);
+
+        // PRG mode 1 swaps 0x8000 and 0xC000
+        mmc3.cpu_write(0x8000, PRG_MODE);
+        assert_eq!(mmc3.cpu_peek(0x8000), 14);
+        assert_eq!(mmc3.cpu_peek(0xA000), 5);
+        assert_eq!(mmc3.cpu_peek(0xC000), 3);
+        assert_eq!(mmc3.cpu_peek(0xE000), 15);
+    }
+
+    #[test]
+    fn test_chr_banking() {
+        let mut mmc3 = mmc3(4, 32);
+        set_bank(&mut mmc3, 0, 9);
+        set_bank(&mut mmc3, 1, 12);
+        for register in 2..6 {
+            set_bank(&mut mmc3, register, 20 + register);
+        }
+
+        // 2 KiB banks ignore the low bit
+        assert_eq!(mmc3.ppu_read(0x0000), 8);
+        assert_eq!(mmc3.ppu_read(0x0400), 9);
+        assert_eq!(mmc3.ppu_read(0x0800), 12);
+        assert_eq!(mmc3.ppu_read(0x0C00), 13);
+        assert_eq!(mmc3.ppu_read(0x1000), 22);
+        assert_eq!(mmc3.ppu_read(0x1C00), 25);
+
+        // Inverted, the 1 KiB banks move to the lower table
+        mmc3.cpu_write(0x8000, CHR_INVERSION);
+        assert_eq!(mmc3.ppu_read(0x0000), 22);
+        assert_eq!(mmc3.ppu_read(0x0C00), 25);
+        assert_eq!(mmc3.ppu_read(0x1000), 8);
+        assert_eq!(mmc3.ppu_read(0x1C00), 13);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mmc3 = mmc3(4, 8);
+        mmc3.cpu_write(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+        mmc3.cpu_write(0xA000, 0);
+        assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut mmc3 = mmc3(4, 8);
+        mmc3.cpu_write(0x6000, 0x11);
+        assert_eq!(mmc3.cpu_peek(0x6000), 0x11);
+
+        // Write protected
+        mmc3.cpu_write(0xA001, PRG_RAM_ENABLE | PRG_RAM_WRITE_PROTECT);
+        mmc3.cpu_write(0x6000, 0x22);
+        assert_eq!(mmc3.cpu_peek(0x6000), 0x11);
+
+        // Disabled altogether
+        mmc3.cpu_write(0xA001, 0);
+        assert_eq!(mmc3.cpu_peek(0x6000), 0);
+    }
+
+    #[test]
+    fn test_empty_prg_rom() {
+        let mut rom = RomBuilder::new(4).build();
+        rom.prg_rom.clear();
+        let mmc3 = Mmc3::new(rom);
+        assert_eq!(mmc3.cpu_peek(0xFFFC), 0);
+    }
+
+    #[test]
+    fn test_prg_ram_size() {
+        assert_eq!(mmc3(4, 8).prg_ram.len(), PRG_RAM_SIZE);
+
+        // NES 2.0 header asking for 32 KiB of battery backed RAM
+        let mut raw = RomBuilder::new(4).submapper(0).raw();
+        raw[10] = 0x90;
+        let mmc3 = Mmc3::new(Rom::new(&raw).unwrap());
+        assert_eq!(mmc3.prg_ram.len(), 32 * 1024);
+    }
+
+    #[test]
+    fn test_irq_counter() {
+        let mut mmc3 = mmc3(4, 8);
+        mmc3.cpu_write(0xC000, 2);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+
+        // Reload to 2, then 1, then 0 fires
+        mmc3.a12_rise();
+        mmc3.a12_rise();
+        assert!(!mmc3.irq());
+        mmc3.a12_rise();
+        assert!(mmc3.irq());
+
+        // Acknowledged by disabling, the counter reloads and carries on
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.irq());
+        mmc3.a12_rise();
+        assert_eq!(mmc3.irq_counter, 2);
+        mmc3.a12_rise();
+        mmc3.a12_rise();
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn test_irq_latch_zero() {
+        let mut mmc3 = mmc3(4, 8);
+        mmc3.cpu_write(0xE001, 0);
+
+        // A latch of 0 fires on every clock
+        for _ in 0..3 {
+            mmc3.a12_rise();
+            assert!(mmc3.irq());
+            mmc3.cpu_write(0xE000, 0);
+            mmc3.cpu_write(0xE001, 0);
+        }
+    }
+}
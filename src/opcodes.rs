@@ -1,16 +1,43 @@
-//pub static mut HISTORY: String = String::new();
-
 /// Generates an opcode and its various specific params
 #[macro_export]
 #[allow(unused)]
 macro_rules! opcode {
+    // Sub module for a single opcode value, named after its addressing
+    // mode unless an alias is given with `as` (needed when an opcode has
+    // several values sharing the same addressing mode)
+    (@mode $name:ident, $value:tt, $length:tt, $cycles:tt, $mode:ident) => (
+        opcode!(@mode $name, $value, $length, $cycles, $mode as $mode);
+    );
+    (@mode $name:ident, $value:tt, $length:tt, $cycles:tt, $mode:ident as $module:ident) => (
+        #[allow(non_camel_case_types, unused, non_snake_case)]
+        pub mod $module {
+            pub const VALUE: u8 = $value;
+            pub const LEN: u8 = $length;
+            pub const CYCLES: u8 = $cycles;
+
+            // Generate an execute function pointer with the specified
+            // addressing mode
+            pub fn execute(cpu: &mut crate::cpu::CPU) {
+                // Page crossing and branch penalties are added
+                // on top of this by the instruction itself
+                cpu.cycles += CYCLES as u64;
+
+                super::execute(cpu, crate::opcodes::AddressingMode::$mode);
+                
+                if (cpu.counter_state == cpu.counter) {
+                    cpu.counter += (LEN - 1) as u16;
+                }
+            }
+        }
+    );
+
     // Assembly name,
     // behavior expression, 
     // opcode # value,
     // memory length, 
     // # of cpu cycles to execute, 
-    // addressing mode
-    ($($name:ident $exec:expr, [$(($value:tt, $length:tt, $cycles:tt, $mode:ident)),*,]),*) => (
+    // addressing mode (optionally `as` a module alias)
+    ($($name:ident $exec:expr, [$(($value:tt, $length:tt, $cycles:tt, $mode:ident $(as $module:ident)?)),*,]),*) => (
         
         // Generate a public module for each opcode value
         #[allow(non_camel_case_types, unused, non_snake_case)]
@@ -19,33 +46,7 @@ macro_rules! opcode {
             // Foreach opcode value create a sub module with the 
             // value, length, and # of cycles to execute
             $(
-                #[allow(non_camel_case_types, unused, non_snake_case)]
-                pub mod $mode {
-                    pub const VALUE: u8 = $value;
-                    pub const LEN: u8 = $length;
-                    pub const CYCLES: u8 = $cycles;
-
-                    // Generate an execute function pointer with the specified
-                    // addressing mode
-                    pub fn execute(cpu: &mut crate::cpu::CPU) {
-                        super::execute(cpu, crate::opcodes::AddressingMode::$mode);
-                        
-                        if (cpu.counter_state == cpu.counter) {
-                            cpu.counter += (super::$mode::LEN - 1) as u16;
-                        }
-                        
-                        // Writes history of execution
-                        // for debugging purposes...
-                        // unsafe {
-                        //     let mut mode = stringify!($mode);
-                        //     if (mode == "NONE_ADDRESSING") {
-                        //         mode = "";
-                        //     }
-                        //     let cmd = String::from(format!("{} {}\n", stringify!($name), mode));
-                        //     crate::opcodes::HISTORY.push_str(&cmd);
-                        // }                  
-                    }
-                }                    
+                opcode!(@mode $name, $value, $length, $cycles, $mode $(as $module)?);
             )*
             
             // Generate an assembly command module level function 
@@ -57,6 +58,20 @@ macro_rules! opcode {
                 $exec(cpu, mode);
             }
         })*
+
+        // Every opcode value generated above
+        pub const VALUES: &[u8] = &[$($($value),*),*];
+
+        // Metadata for every opcode generated above, used for tracing
+        pub const TABLE: &[crate::opcodes::Opcode] = &[$($(
+            crate::opcodes::Opcode {
+                value: $value,
+                name: stringify!($name),
+                len: $length,
+                cycles: $cycles,
+                mode: crate::opcodes::AddressingMode::$mode,
+            }
+        ),*),*];
     )
 }
 
@@ -78,9 +93,13 @@ macro_rules! opcode {
 // }
 
 opcode![
-    // Special case
+    // Force Interrupt
+    // The byte after BRK is padding, the pushed return address skips it
     BRK |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
-        return;
+        use crate::cpu::Interrupt;
+
+        cpu.counter = cpu.counter.wrapping_add(1);
+        cpu.interrupt(Interrupt::BRK);
     }, [
         (0x00, 1, 7, NONE_ADDRESSING),
     ],
@@ -96,10 +115,9 @@ opcode![
     // Add with carry
     // A,Z,C,N = A + M + C
     ADC |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
-        use crate::cpu::{CPU, Memory};
+        use crate::cpu::CPU;
 
-        let addr = cpu.get_operand_addr(mode);
-        let value = cpu.mem_read(addr);
+        let value = cpu.read_operand(mode);
         
         cpu.register_a_add(value);
     }, [
@@ -107,19 +125,18 @@ opcode![
         (0x65, 2, 3, ZERO_PAGE),
         (0x75, 2, 4, ZERO_PAGE_X),
         (0x6D, 3, 4, ABSOLUTE),
-        (0x7D, 3, 4, ABSOLUTE_X),
-        (0x79, 3, 4, ABSOLUTE_Y),
+        (0x7D, 3, 4, ABSOLUTE_X), // +1 if page crossed
+        (0x79, 3, 4, ABSOLUTE_Y), // +1 if page crossed
         (0x61, 2, 6, INDIRECT_X),
-        (0x71, 2, 5, INDIRECT_Y),
+        (0x71, 2, 5, INDIRECT_Y), // +1 if page crossed
     ],
 
     // Subtract with Carry
     // A,Z,C,N = A-M-(1-C)
     SBC |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
-        use crate::cpu::{CPU, Memory};
+        use crate::cpu::CPU;
 
-        let addr = cpu.get_operand_addr(mode);
-        let data = cpu.mem_read(addr) as i8;
+        let data = cpu.read_operand(mode) as i8;
 
         cpu.register_a_add(data.wrapping_neg().wrapping_sub(1) as u8);
     }, [
@@ -136,10 +153,9 @@ opcode![
     // Logical And
     // A,Z,N = A & M
     AND |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
-        use crate::cpu::{Memory, Flag};
+        use crate::cpu::Flag;
 
-        let addr = cpu.get_operand_addr(mode);
-        let data = cpu.mem_read(addr);
+        let data = cpu.read_operand(mode);
 
         cpu.register_a &= data;
 
@@ -150,19 +166,18 @@ opcode![
         (0x25, 2, 3, ZERO_PAGE),
         (0x35, 2, 4, ZERO_PAGE_X),
         (0x2D, 3, 4, ABSOLUTE),
-        (0x3D, 3, 4, ABSOLUTE_X),
-        (0x39, 3, 4, ABSOLUTE_Y),
+        (0x3D, 3, 4, ABSOLUTE_X), // +1 if page crossed
+        (0x39, 3, 4, ABSOLUTE_Y), // +1 if page crossed
         (0x21, 2, 6, INDIRECT_X),
-        (0x31, 2, 5, INDIRECT_Y),
+        (0x31, 2, 5, INDIRECT_Y), // +1 if page crossed
     ],
 
     // Exclusive Or
     // A,Z,N = A^M
     EOR |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
-        use crate::cpu::{Memory, Flag};
+        use crate::cpu::Flag;
 
-        let addr = cpu.get_operand_addr(mode);
-        let data = cpu.mem_read(addr);
+        let data = cpu.read_operand(mode);
 
         cpu.register_a ^= data;
 
@@ -183,10 +198,9 @@ opcode![
     // Logical Inclusive Or
     // A,Z,N = A|M
     ORA |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
-        use crate::cpu::{Memory, Flag};
+        use crate::cpu::Flag;
 
-        let addr = cpu.get_operand_addr(mode);
-        let data = cpu.mem_read(addr);
+        let data = cpu.read_operand(mode);
 
         cpu.register_a |= data;
 
@@ -201,17 +215,17 @@ opcode![
         (0x1D, 3, 4, ABSOLUTE_X), // +1 if page crossed
         (0x19, 3, 4, ABSOLUTE_Y), // +1 if page crossed
         (0x01, 2, 6, INDIRECT_X),
-        (0x22, 2, 5, INDIRECT_Y), // +1 if page crossed
+        (0x11, 2, 5, INDIRECT_Y), // +1 if page crossed
     ],
 
     // Decrement memory
     DEC |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
         cpu.decrement_memory(mode);
     }, [
-        (0x25, 2, 5, ZERO_PAGE),
-        (0x35, 2, 6, ZERO_PAGE_X),
-        (0x2D, 3, 6, ABSOLUTE),
-        (0x29, 3, 7, ABSOLUTE_X), 
+        (0xC6, 2, 5, ZERO_PAGE),
+        (0xD6, 2, 6, ZERO_PAGE_X),
+        (0xCE, 3, 6, ABSOLUTE),
+        (0xDE, 3, 7, ABSOLUTE_X),
     ],
 
     /* Shifts */
@@ -220,48 +234,21 @@ opcode![
     // or 
     // M,Z,C,N = M * 2
     ASL |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
-        use crate::cpu::{Memory, Flag};
         use crate::opcodes::AddressingMode;
-        
-        let mut data;
 
-        match mode {
-            AddressingMode::NONE_ADDRESSING => {
-                data = cpu.register_a;
-
-                // If a bit is left over set Carry flag
-                match data >> 7 {
-                    1 => cpu.status.insert(Flag::Carry),
-                    _ => cpu.status.remove(Flag::Carry)
-                }
-        
-                data <<= 1;
-            }
-            _ => {
-                let addr = cpu.get_operand_addr(mode);
-                data = cpu.mem_read(addr);
-        
-                // If a bit is left over set Carry flag
-                match data >> 7 {
-                    1 => cpu.status.insert(Flag::Carry),
-                    _ => cpu.status.remove(Flag::Carry)
-                }
-        
-                data <<= 1;
-                cpu.mem_write(addr, data);
-            }
-        };
+        if matches!(mode, AddressingMode::NONE_ADDRESSING) {
+            cpu.arithmetic_shift_left_a();
+            return;
+        }
 
-        cpu.update_flag(Flag::Zero, data);
-        cpu.update_flag(Flag::Negative, data);
-        return data;
+        _ = cpu.arithmetic_shift_left(mode);
 
     }, [
-        (0x2A, 1, 2, NONE_ADDRESSING), // Accumulator
-        (0x26, 2, 5, ZERO_PAGE),
-        (0x36, 2, 6, ZERO_PAGE_X),
-        (0x2E, 3, 6, ABSOLUTE),
-        (0x3E, 3, 7, ABSOLUTE_X),
+        (0x0A, 1, 2, NONE_ADDRESSING), // Accumulator
+        (0x06, 2, 5, ZERO_PAGE),
+        (0x16, 2, 6, ZERO_PAGE_X),
+        (0x0E, 3, 6, ABSOLUTE),
+        (0x1E, 3, 7, ABSOLUTE_X),
     ],
     
     // Rotate Left
@@ -276,11 +263,11 @@ opcode![
         _ = cpu.rotate_left(mode);
 
     }, [
-        (0x0A, 1, 2, NONE_ADDRESSING), // Accumulator
-        (0x06, 2, 5, ZERO_PAGE),
-        (0x16, 2, 6, ZERO_PAGE_X),
-        (0x0E, 3, 6, ABSOLUTE),
-        (0x1E, 3, 7, ABSOLUTE_X),
+        (0x2A, 1, 2, NONE_ADDRESSING), // Accumulator
+        (0x26, 2, 5, ZERO_PAGE),
+        (0x36, 2, 6, ZERO_PAGE_X),
+        (0x2E, 3, 6, ABSOLUTE),
+        (0x3E, 3, 7, ABSOLUTE_X),
     ],
 
     ROR |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
@@ -301,26 +288,6 @@ opcode![
         (0x7E, 3, 7, ABSOLUTE_X),
     ],
 
-    // Decrement Memory
-    DEM |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
-        use crate::cpu::{Flag, Memory};
-        
-        let addr = cpu.get_operand_addr(mode);
-        let mut data = cpu.mem_read(addr);
-        
-        data = data.wrapping_sub(1);
-
-        cpu.mem_write(addr, data);
-        cpu.update_flag(Flag::Zero, data);
-        cpu.update_flag(Flag::Negative, data);
-
-    }, [
-        (0xC6, 2, 5, ZERO_PAGE),
-        (0xD6, 2, 6, ZERO_PAGE_X),
-        (0xCE, 3, 6, ABSOLUTE),
-        (0xDE, 3, 7, ABSOLUTE_X),
-    ],
-
     // Decrement X Register
     // X,Z,N = X - 1
     DEX |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
@@ -352,7 +319,7 @@ opcode![
         (0xC9, 2, 2, IMMEDIATE),
         (0xC5, 2, 3, ZERO_PAGE),
         (0xD5, 2, 4, ZERO_PAGE_X),
-        (0xCD, 2, 4, ABSOLUTE),
+        (0xCD, 3, 4, ABSOLUTE),
         (0xDD, 3, 4, ABSOLUTE_X), // +1 if page crossed
         (0xD9, 3, 4, ABSOLUTE_Y), // +1 if page crossed
         (0xC1, 2, 6, INDIRECT_X),
@@ -425,7 +392,7 @@ opcode![
 
         cpu.status = Flag::from_bits_truncate(cpu.stack_pull());
         cpu.status.remove(Flag::BreakCommand);
-        cpu.status.remove(Flag::BreakCommand2);
+        cpu.status.insert(Flag::BreakCommand2);
 
         cpu.counter = cpu.stack_pull_u16();
     }, [
@@ -547,8 +514,8 @@ opcode![
         (0xA9, 2, 2, IMMEDIATE),
         (0xA5, 2, 3, ZERO_PAGE),
         (0xB5, 2, 4, ZERO_PAGE_X),
-        (0xAD, 3, 4, ABSOLUTE), // +1 if page crossed
-        (0xBD, 3, 4, ABSOLUTE_X),
+        (0xAD, 3, 4, ABSOLUTE),
+        (0xBD, 3, 4, ABSOLUTE_X), // +1 if page crossed
         (0xB9, 3, 4, ABSOLUTE_Y), // +1 if page crossed 
         (0xA1, 2, 6, INDIRECT_X),
         (0xB1, 2, 5, INDIRECT_Y), // +1 if page crossed
@@ -600,7 +567,7 @@ opcode![
         cpu.mem_write(addr, cpu.register_x);
     }, [
         (0x86, 2, 3, ZERO_PAGE),
-        (0x96, 2, 4, ZERO_PAGE_X),
+        (0x96, 2, 4, ZERO_PAGE_Y),
         (0x8E, 3, 4, ABSOLUTE),
     ],
 
@@ -672,18 +639,7 @@ opcode![
     // Adds one to the value held at a specified memory location setting the 
     // zero and negative flags as appropriate.
     INC |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
-        
-        use crate::cpu::{Memory};
-
-        let addr = cpu.get_operand_addr(mode);
-        let mut data = cpu.mem_read(addr);
-        data = data.wrapping_add(1);
-
-        cpu.mem_write(addr, data);
-
-        cpu.update_flag(crate::cpu::Flag::Zero, data);
-        cpu.update_flag(crate::cpu::Flag::Negative, data);
-
+        cpu.increment_memory(mode);
     }, [
         (0xE6, 2, 5, ZERO_PAGE),
         (0xF6, 2, 6, ZERO_PAGE_X),
@@ -707,7 +663,7 @@ opcode![
         cpu.update_flag(crate::cpu::Flag::Zero, cpu.register_y);
         cpu.update_flag(crate::cpu::Flag::Negative, cpu.register_y);
     }, [
-        (0xE8, 1, 2, NONE_ADDRESSING),
+        (0xC8, 1, 2, NONE_ADDRESSING),
     ],
     
     // Set Carry Flag
@@ -806,7 +762,12 @@ opcode![
     ],
 
     PLA |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+        use crate::cpu::Flag;
+
         cpu.register_a = cpu.stack_pull();
+
+        cpu.update_flag(Flag::Zero, cpu.register_a);
+        cpu.update_flag(Flag::Negative, cpu.register_a);
     }, [
         (0x68, 1, 4, NONE_ADDRESSING),
     ],
@@ -816,13 +777,239 @@ opcode![
 
         cpu.status = Flag::from_bits_truncate(cpu.stack_pull());
         cpu.status.remove(Flag::BreakCommand);
-        cpu.status.remove(Flag::BreakCommand2);
+        cpu.status.insert(Flag::BreakCommand2);
     }, [
         (0x28, 1, 4, NONE_ADDRESSING),
     ]
 ];
 
-#[derive(Debug)]
+/// Name, length, cycles and addressing mode of an opcode value
+#[derive(Debug, Clone, Copy)]
+pub struct Opcode {
+    pub value: u8,
+    pub name: &'static str,
+    pub len: u8,
+    pub cycles: u8,
+    pub mode: AddressingMode,
+}
+
+/// Finds the metadata for byte_code, official opcodes first
+pub fn lookup(byte_code: u8) -> Option<&'static Opcode> {
+    return TABLE.iter()
+        .chain(illegal::TABLE.iter())
+        .find(|opcode| opcode.value == byte_code);
+}
+
+pub fn is_illegal(byte_code: u8) -> bool {
+    return !VALUES.contains(&byte_code) && illegal::VALUES.contains(&byte_code);
+}
+
+/// Undocumented opcodes, stable ones only. Many commercial games and test
+/// ROMs (nestest) rely on these.
+/// https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+pub mod illegal {
+    use super::AddressingMode;
+
+    opcode![
+        // Load A and X
+        // A,X,Z,N = M
+        LAX |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            use crate::cpu::{Flag, Register};
+
+            cpu.load_into(mode, Register::A);
+            cpu.register_x = cpu.register_a;
+        }, [
+            (0xA7, 2, 3, ZERO_PAGE),
+            (0xB7, 2, 4, ZERO_PAGE_Y),
+            (0xAF, 3, 4, ABSOLUTE),
+            (0xBF, 3, 4, ABSOLUTE_Y), // +1 if page crossed
+            (0xA3, 2, 6, INDIRECT_X),
+            (0xB3, 2, 5, INDIRECT_Y), // +1 if page crossed
+        ],
+
+        // Store A & X
+        // M = A & X
+        SAX |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            use crate::cpu::Memory;
+
+            let addr = cpu.get_operand_addr(mode);
+            cpu.mem_write(addr, cpu.register_a & cpu.register_x);
+        }, [
+            (0x87, 2, 3, ZERO_PAGE),
+            (0x97, 2, 4, ZERO_PAGE_Y),
+            (0x8F, 3, 4, ABSOLUTE),
+            (0x83, 2, 6, INDIRECT_X),
+        ],
+
+        // Decrement memory then compare with A
+        // M = M - 1, Z,C,N = A - M
+        DCP |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            let data = cpu.decrement_memory(mode);
+            cpu.compare_data(data, cpu.register_a);
+        }, [
+            (0xC7, 2, 5, ZERO_PAGE),
+            (0xD7, 2, 6, ZERO_PAGE_X),
+            (0xCF, 3, 6, ABSOLUTE),
+            (0xDF, 3, 7, ABSOLUTE_X),
+            (0xDB, 3, 7, ABSOLUTE_Y),
+            (0xC3, 2, 8, INDIRECT_X),
+            (0xD3, 2, 8, INDIRECT_Y),
+        ],
+
+        // Increment memory then subtract with carry
+        // M = M + 1, A,Z,C,N,V = A - M - (1 - C)
+        ISB |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            let data = cpu.increment_memory(mode);
+            cpu.register_a_add(!data);
+        }, [
+            (0xE7, 2, 5, ZERO_PAGE),
+            (0xF7, 2, 6, ZERO_PAGE_X),
+            (0xEF, 3, 6, ABSOLUTE),
+            (0xFF, 3, 7, ABSOLUTE_X),
+            (0xFB, 3, 7, ABSOLUTE_Y),
+            (0xE3, 2, 8, INDIRECT_X),
+            (0xF3, 2, 8, INDIRECT_Y),
+        ],
+
+        // Shift left then inclusive or
+        // M = M * 2, A,Z,N = A | M
+        SLO |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            use crate::cpu::Flag;
+
+            let data = cpu.arithmetic_shift_left(mode);
+            cpu.register_a |= data;
+
+            cpu.update_flag(Flag::Zero, cpu.register_a);
+            cpu.update_flag(Flag::Negative, cpu.register_a);
+        }, [
+            (0x07, 2, 5, ZERO_PAGE),
+            (0x17, 2, 6, ZERO_PAGE_X),
+            (0x0F, 3, 6, ABSOLUTE),
+            (0x1F, 3, 7, ABSOLUTE_X),
+            (0x1B, 3, 7, ABSOLUTE_Y),
+            (0x03, 2, 8, INDIRECT_X),
+            (0x13, 2, 8, INDIRECT_Y),
+        ],
+
+        // Rotate left then and
+        // M = M rol 1, A,Z,N = A & M
+        RLA |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            use crate::cpu::Flag;
+
+            let data = cpu.rotate_left(mode);
+            cpu.register_a &= data;
+
+            cpu.update_flag(Flag::Zero, cpu.register_a);
+            cpu.update_flag(Flag::Negative, cpu.register_a);
+        }, [
+            (0x27, 2, 5, ZERO_PAGE),
+            (0x37, 2, 6, ZERO_PAGE_X),
+            (0x2F, 3, 6, ABSOLUTE),
+            (0x3F, 3, 7, ABSOLUTE_X),
+            (0x3B, 3, 7, ABSOLUTE_Y),
+            (0x23, 2, 8, INDIRECT_X),
+            (0x33, 2, 8, INDIRECT_Y),
+        ],
+
+        // Shift right then exclusive or
+        // M = M / 2, A,Z,N = A ^ M
+        SRE |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            use crate::cpu::Flag;
+
+            let data = cpu.logical_shift_right(mode);
+            cpu.register_a ^= data;
+
+            cpu.update_flag(Flag::Zero, cpu.register_a);
+            cpu.update_flag(Flag::Negative, cpu.register_a);
+        }, [
+            (0x47, 2, 5, ZERO_PAGE),
+            (0x57, 2, 6, ZERO_PAGE_X),
+            (0x4F, 3, 6, ABSOLUTE),
+            (0x5F, 3, 7, ABSOLUTE_X),
+            (0x5B, 3, 7, ABSOLUTE_Y),
+            (0x43, 2, 8, INDIRECT_X),
+            (0x53, 2, 8, INDIRECT_Y),
+        ],
+
+        // Rotate right then add with carry
+        // M = M ror 1, A,Z,C,N,V = A + M + C
+        RRA |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            let data = cpu.rotate_right(mode);
+            cpu.register_a_add(data);
+        }, [
+            (0x67, 2, 5, ZERO_PAGE),
+            (0x77, 2, 6, ZERO_PAGE_X),
+            (0x6F, 3, 6, ABSOLUTE),
+            (0x7F, 3, 7, ABSOLUTE_X),
+            (0x7B, 3, 7, ABSOLUTE_Y),
+            (0x63, 2, 8, INDIRECT_X),
+            (0x73, 2, 8, INDIRECT_Y),
+        ],
+
+        // Same as the official SBC immediate
+        SBC |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            let data = cpu.read_operand(mode);
+            cpu.register_a_add(!data);
+        }, [
+            (0xEB, 2, 2, IMMEDIATE),
+        ],
+
+        // NOPs of every length, the ones with an operand still read it
+        NOP |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            if !matches!(mode, super::AddressingMode::NONE_ADDRESSING) {
+                _ = cpu.read_operand(mode);
+            }
+        }, [
+            (0x1A, 1, 2, NONE_ADDRESSING as NONE_ADDRESSING_1A),
+            (0x3A, 1, 2, NONE_ADDRESSING as NONE_ADDRESSING_3A),
+            (0x5A, 1, 2, NONE_ADDRESSING as NONE_ADDRESSING_5A),
+            (0x7A, 1, 2, NONE_ADDRESSING as NONE_ADDRESSING_7A),
+            (0xDA, 1, 2, NONE_ADDRESSING as NONE_ADDRESSING_DA),
+            (0xFA, 1, 2, NONE_ADDRESSING as NONE_ADDRESSING_FA),
+            (0x80, 2, 2, IMMEDIATE as IMMEDIATE_80),
+            (0x82, 2, 2, IMMEDIATE as IMMEDIATE_82),
+            (0x89, 2, 2, IMMEDIATE as IMMEDIATE_89),
+            (0xC2, 2, 2, IMMEDIATE as IMMEDIATE_C2),
+            (0xE2, 2, 2, IMMEDIATE as IMMEDIATE_E2),
+            (0x04, 2, 3, ZERO_PAGE as ZERO_PAGE_04),
+            (0x44, 2, 3, ZERO_PAGE as ZERO_PAGE_44),
+            (0x64, 2, 3, ZERO_PAGE as ZERO_PAGE_64),
+            (0x14, 2, 4, ZERO_PAGE_X as ZERO_PAGE_X_14),
+            (0x34, 2, 4, ZERO_PAGE_X as ZERO_PAGE_X_34),
+            (0x54, 2, 4, ZERO_PAGE_X as ZERO_PAGE_X_54),
+            (0x74, 2, 4, ZERO_PAGE_X as ZERO_PAGE_X_74),
+            (0xD4, 2, 4, ZERO_PAGE_X as ZERO_PAGE_X_D4),
+            (0xF4, 2, 4, ZERO_PAGE_X as ZERO_PAGE_X_F4),
+            (0x0C, 3, 4, ABSOLUTE),
+            (0x1C, 3, 4, ABSOLUTE_X as ABSOLUTE_X_1C), // +1 if page crossed
+            (0x3C, 3, 4, ABSOLUTE_X as ABSOLUTE_X_3C), // +1 if page crossed
+            (0x5C, 3, 4, ABSOLUTE_X as ABSOLUTE_X_5C), // +1 if page crossed
+            (0x7C, 3, 4, ABSOLUTE_X as ABSOLUTE_X_7C), // +1 if page crossed
+            (0xDC, 3, 4, ABSOLUTE_X as ABSOLUTE_X_DC), // +1 if page crossed
+            (0xFC, 3, 4, ABSOLUTE_X as ABSOLUTE_X_FC), // +1 if page crossed
+        ],
+
+        // Locks up the CPU until reset, the counter never moves past it
+        JAM |cpu: &mut crate::cpu::CPU, mode: super::AddressingMode| {
+            cpu.jam();
+        }, [
+            (0x02, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_02),
+            (0x12, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_12),
+            (0x22, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_22),
+            (0x32, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_32),
+            (0x42, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_42),
+            (0x52, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_52),
+            (0x62, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_62),
+            (0x72, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_72),
+            (0x92, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_92),
+            (0xB2, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_B2),
+            (0xD2, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_D2),
+            (0xF2, 1, 0, NONE_ADDRESSING as NONE_ADDRESSING_F2),
+        ]
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types, unused)]
 pub enum AddressingMode {
     IMMEDIATE,
@@ -0,0 +1,180 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::ppu::Mask;
+
+// RGB values of the 64 colours the 2C02 can output, indexed by the 6 bit
+// values stored in palette RAM. 0x0D is "blacker than black" and the
+// 0x?E / 0x?F columns are all black.
+pub static SYSTEM_PALETTE: [(u8, u8, u8); 64] = [
+    (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96),
+    (0xA1, 0x00, 0x5E), (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00),
+    (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00), (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E),
+    (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05), (0x05, 0x05, 0x05),
+    (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+    (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00),
+    (0xC4, 0x62, 0x00), (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55),
+    (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21), (0x09, 0x09, 0x09), (0x09, 0x09, 0x09),
+    (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF), (0xD4, 0x80, 0xFF),
+    (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+    (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4),
+    (0x05, 0xFB, 0xFF), (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D),
+    (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF), (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB),
+    (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0), (0xFF, 0xEF, 0xA6),
+    (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
+];
+
+// 64 RGB triplets
+const PALETTE_SIZE: usize = 64 * 3;
+// 8 sets of 64, one for every combination of the emphasis bits
+const EMPHASIS_PALETTE_SIZE: usize = 8 * PALETTE_SIZE;
+
+// How much emphasis dims the channels it isn't emphasising
+const ATTENUATION: f32 = 0.816328;
+
+#[derive(Debug)]
+pub enum PaletteError {
+    // Neither 192 nor 1536 bytes long
+    InvalidSize(usize),
+    Io(io::Error),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::InvalidSize(size) => write!(
+                f, "palette is {} bytes, expected {} or {}", size, PALETTE_SIZE, EMPHASIS_PALETTE_SIZE),
+            PaletteError::Io(err) => write!(f, "failed to read palette: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<io::Error> for PaletteError {
+    fn from(err: io::Error) -> Self {
+        PaletteError::Io(err)
+    }
+}
+
+/// Colours of all 64 palette values under each of the 8 combinations of
+/// the PPUMASK emphasis bits.
+pub struct Palette {
+    colors: Vec<(u8, u8, u8)>,
+}
+
+impl Palette {
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette, PaletteError> {
+        let raw = fs::read(path)?;
+        return Palette::new(&raw);
+    }
+
+    // Parses a .pal file, 64 RGB triplets or 512 with the emphasised sets
+    // following the plain one. Without them emphasis is approximated.
+    pub fn new(raw: &[u8]) -> Result<Palette, PaletteError> {
+        let colors: Vec<(u8, u8, u8)> = raw
+            .chunks_exact(3)
+            .map(|rgb| (rgb[0], rgb[1], rgb[2]))
+            .collect();
+
+        return match raw.len() {
+            PALETTE_SIZE => Ok(Palette::with_emphasis(&colors)),
+            EMPHASIS_PALETTE_SIZE => Ok(Palette { colors }),
+            size => Err(PaletteError::InvalidSize(size)),
+        };
+    }
+
+    fn with_emphasis(base: &[(u8, u8, u8)]) -> Palette {
+        let mut colors = Vec::with_capacity(base.len() * 8);
+        for emphasis in 0..8 {
+            colors.extend(base.iter().map(|&rgb| emphasize(rgb, emphasis)));
+        }
+
+        return Palette { colors };
+    }
+
+    // RGB of a palette RAM value with the greyscale and emphasis bits of
+    // PPUMASK applied
+    pub fn color(&self, value: u8, mask: Mask) -> (u8, u8, u8) {
+        let mut value = value & 0x3F;
+        // Greyscale keeps only the grey column
+        if mask.contains(Mask::Greyscale) {
+            value &= 0x30;
+        }
+
+        let emphasis = (mask.bits() >> 5) as usize;
+        return self.colors[emphasis * 64 + value as usize];
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        return Palette::with_emphasis(&SYSTEM_PALETTE);
+    }
+}
+
+// Every emphasis bit (red, green, blue from bit 0) dims the other two
+// channels
+fn emphasize(rgb: (u8, u8, u8), emphasis: u8) -> (u8, u8, u8) {
+    let dim = |channel: u8, others: u8| -> u8 {
+        match emphasis & others {
+            0 => channel,
+            _ => (channel as f32 * ATTENUATION) as u8,
+        }
+    };
+
+    return (dim(rgb.0, 0b110), dim(rgb.1, 0b101), dim(rgb.2, 0b011));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_matches_system_palette() {
+        let palette = Palette::default();
+        for value in 0..64 {
+            assert_eq!(palette.color(value, Mask::empty()), SYSTEM_PALETTE[value as usize]);
+        }
+    }
+
+    #[test]
+    fn test_load_pal() {
+        let mut raw = vec![0; PALETTE_SIZE];
+        raw[3 * 0x21..3 * 0x22].copy_from_slice(&[0x10, 0x20, 0x30]);
+
+        let palette = Palette::new(&raw).unwrap();
+        assert_eq!(palette.color(0x21, Mask::empty()), (0x10, 0x20, 0x30));
+        // Values only use 6 bits
+        assert_eq!(palette.color(0x61, Mask::empty()), (0x10, 0x20, 0x30));
+    }
+
+    #[test]
+    fn test_load_pal_with_emphasis() {
+        let mut raw = vec![0; EMPHASIS_PALETTE_SIZE];
+        // Value 0x05 with blue emphasis, the 5th set
+        let entry = 3 * (4 * 64 + 0x05);
+        raw[entry..entry + 3].copy_from_slice(&[1, 2, 3]);
+
+        let palette = Palette::new(&raw).unwrap();
+        assert_eq!(palette.color(0x05, Mask::EmphasizeBlue), (1, 2, 3));
+        assert_eq!(palette.color(0x05, Mask::empty()), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        assert!(matches!(Palette::new(&[0; 100]), Err(PaletteError::InvalidSize(100))));
+    }
+
+    #[test]
+    fn test_greyscale_and_emphasis() {
+        let palette = Palette::default();
+
+        assert_eq!(palette.color(0x16, Mask::Greyscale), SYSTEM_PALETTE[0x10]);
+
+        // Red emphasis dims green and blue
+        let (r, g, b) = palette.color(0x30, Mask::EmphasizeRed);
+        assert_eq!((r, g, b), (0xFF, 0xD0, 0xD0));
+    }
+}
@@ -0,0 +1,751 @@
+use bitflags::bitflags;
+
+use crate::cartridge::Mirroring;
+use crate::frame::Frame;
+use crate::mapper::SharedMapper;
+use crate::palette::Palette;
+use crate::render;
+
+// CPU visible registers, mirrored every 8 bytes up to 0x3FFF by the bus
+pub const PPUCTRL: u16 = 0x2000;
+pub const PPUMASK: u16 = 0x2001;
+pub const PPUSTATUS: u16 = 0x2002;
+pub const OAMADDR: u16 = 0x2003;
+pub const OAMDATA: u16 = 0x2004;
+pub const PPUSCROLL: u16 = 0x2005;
+pub const PPUADDR: u16 = 0x2006;
+pub const PPUDATA: u16 = 0x2007;
+
+//  _______________ $4000
+// | Mirrors       |
+// | $0000-$3FFF   |
+// |_______________| $3F20 (mirrors of $3F00-$3F1F up to $3FFF)
+// | Palettes      |
+// |_______________| $3F00
+// | Mirrors       |
+// | $2000-$2EFF   |
+// |_______________| $3000
+// | Nametables    |
+// |_______________| $2000
+// | Pattern       |
+// | Tables        |
+// |_______________| $0000
+
+const PATTERN_TABLES_END: u16 = 0x1FFF;
+const NAMETABLES: u16 = 0x2000;
+const NAMETABLES_MIRRORS_END: u16 = 0x3EFF;
+const PALETTES: u16 = 0x3F00;
+const PALETTES_MIRRORS_END: u16 = 0x3FFF;
+// Only 14 bits of the PPU address bus are wired
+const VRAM_ADDR_MASK: u16 = 0x3FFF;
+
+// NTSC timing
+pub const DOTS_PER_SCANLINE: u16 = 341;
+pub const SCANLINES_PER_FRAME: u16 = 262;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+
+// Pattern table fetches put the upper table on the bus by raising A12
+const PATTERN_TABLE_A12: u16 = 0x1000;
+// MMC3 style counters only see a rise after A12 was low for 3 falls of
+// M2, up to 12 dots. That skips the dips between tile fetches, including
+// the one around the end of the line.
+const A12_FILTER_DOTS: u16 = 12;
+
+// Loopy v / t layout, yyy NN YYYYY XXXXX
+// fine Y, nametable, coarse Y, coarse X
+const COARSE_X: u16 = 0b000_00_00000_11111;
+const COARSE_Y: u16 = 0b000_00_11111_00000;
+const NAMETABLE_X: u16 = 0b000_01_00000_00000;
+const NAMETABLE_Y: u16 = 0b000_10_00000_00000;
+const NAMETABLE_BITS: u16 = 0b000_11_00000_00000;
+const FINE_Y: u16 = 0b111_00_00000_00000;
+// Bits copied from t to v at the end of each scanline and pre-render line
+const HORIZONTAL_BITS: u16 = NAMETABLE_X | COARSE_X;
+const VERTICAL_BITS: u16 = FINE_Y | NAMETABLE_Y | COARSE_Y;
+
+pub struct PPU {
+    // Supplies the pattern tables and picks the nametable mirroring,
+    // shared with the CPU bus
+    pub cartridge: SharedMapper,
+    // 2 KiB on the console, four screen carts supply the other 2 KiB
+    pub vram: [u8; 4096],
+    pub palette: [u8; 32],
+    pub oam: [u8; 256],
+    // RGB output of the palette RAM values, swappable with a .pal file
+    pub system_palette: Palette,
+
+    pub ctrl: Control,
+    pub mask: Mask,
+    pub status: Status,
+    pub oam_addr: u8,
+
+    // Internal scroll registers, see
+    // https://www.nesdev.org/wiki/PPU_scrolling
+    // Current VRAM address, PPUDATA reads and writes through it
+    pub v: u16,
+    // Temporary VRAM address, the top left of the screen once copied to v
+    pub t: u16,
+    pub fine_x: u8,
+    // First / second write toggle shared by PPUSCROLL and PPUADDR
+    w: bool,
+
+    // Dot being drawn, 0 - 340 of scanline 0 - 261
+    pub scanline: u16,
+    pub dot: u16,
+    // Frames completed since power on, bumped when VBlank starts
+    pub frames: u64,
+    // Picture drawn so far, complete once VBlank starts
+    pub frame: Frame,
+    // Raised at the start of VBlank, taken by the CPU
+    nmi_pending: bool,
+
+    // PPUDATA reads below the palettes return the previous read
+    read_buffer: u8,
+    // Last value put on the data bus, write only registers read it back
+    open_bus: u8,
+
+    // Level of PPU address line A12 and how long it has been low
+    a12: bool,
+    a12_low_dots: u16,
+}
+
+impl PPU {
+
+    // Pattern tables from an NROM board, CHR RAM when chr_rom is empty
+    #[cfg(test)]
+    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        return PPU::with_cartridge(crate::mapper::shared(crate::mapper::Nrom::new(vec![], chr_rom, mirroring)));
+    }
+
+    pub fn with_cartridge(cartridge: SharedMapper) -> Self {
+        PPU {
+            cartridge,
+            vram: [0; 4096],
+            palette: [0; 32],
+            oam: [0; 256],
+            system_palette: Palette::default(),
+            ctrl: Control::empty(),
+            mask: Mask::empty(),
+            status: Status::empty(),
+            oam_addr: 0,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false,
+            scanline: 0,
+            dot: 0,
+            frames: 0,
+            frame: Frame::new(),
+            nmi_pending: false,
+            read_buffer: 0,
+            open_bus: 0,
+            a12: false,
+            a12_low_dots: 0,
+        }
+    }
+
+    // Register reads as the CPU sees them, with their side effects
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        let data = self.peek_register(addr);
+
+        match addr {
+            PPUSTATUS => {
+                self.status.remove(Status::VBlank);
+                self.w = false;
+            }
+            PPUDATA => {
+                let vram_addr = self.v & VRAM_ADDR_MASK;
+                // Palettes are returned straight away but the buffer is
+                // still filled, with the nametable byte "under" them
+                self.read_buffer = match vram_addr {
+                    PALETTES ..= PALETTES_MIRRORS_END => self.read_vram(vram_addr - 0x1000),
+                    _ => self.read_vram(vram_addr),
+                };
+                self.increment_vram_addr();
+            }
+            _ => {}
+        }
+
+        self.open_bus = data;
+        return data;
+    }
+
+    // Register reads without side effects, for tracing and debuggers
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        return match addr {
+            // Only the top 3 bits are driven, the rest is stale bus
+            PPUSTATUS => (self.status.bits() & 0b1110_0000) | (self.open_bus & 0b0001_1111),
+            OAMDATA => self.oam[self.oam_addr as usize],
+            PPUDATA => {
+                let vram_addr = self.v & VRAM_ADDR_MASK;
+                match vram_addr {
+                    PALETTES ..= PALETTES_MIRRORS_END => self.read_vram(vram_addr),
+                    _ => self.read_buffer,
+                }
+            }
+            // PPUCTRL, PPUMASK, OAMADDR, PPUSCROLL and PPUADDR are write only
+            _ => self.open_bus,
+        };
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+
+        match addr {
+            PPUCTRL => {
+                let nmi_enabled = self.ctrl.contains(Control::GenerateNmi);
+                self.ctrl = Control::from_bits_truncate(data);
+                self.t = (self.t & !NAMETABLE_BITS) | ((data as u16 & 0b11) << 10);
+
+                // Enabling NMI during VBlank fires one straight away
+                if !nmi_enabled && self.ctrl.contains(Control::GenerateNmi)
+                    && self.status.contains(Status::VBlank) {
+                    self.nmi_pending = true;
+                }
+            }
+            PPUMASK => self.mask = Mask::from_bits_truncate(data),
+            PPUSTATUS => {}
+            OAMADDR => self.oam_addr = data,
+            OAMDATA => {
+                self.oam[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            PPUSCROLL => {
+                let data = data as u16;
+                match self.w {
+                    // X, coarse into t and fine into x
+                    false => {
+                        self.t = (self.t & !COARSE_X) | (data >> 3);
+                        self.fine_x = data as u8 & 0b111;
+                    }
+                    // Y, both into t
+                    true => {
+                        self.t = (self.t & !(FINE_Y | COARSE_Y))
+                            | ((data & 0b111) << 12)
+                            | ((data >> 3) << 5);
+                    }
+                }
+                self.w = !self.w;
+            }
+            PPUADDR => {
+                // High byte first, then low byte which also copies t to v
+                match self.w {
+                    false => {
+                        self.t = (self.t & 0x00FF) | ((data as u16 & 0x3F) << 8);
+                    }
+                    true => {
+                        self.t = (self.t & 0xFF00) | data as u16;
+                        self.v = self.t;
+                    }
+                }
+                self.w = !self.w;
+            }
+            PPUDATA => {
+                self.write_vram(self.v, data);
+                self.increment_vram_addr();
+            }
+            _ => {}
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        let step = match self.ctrl.contains(Control::VramIncrement) {
+            true => 32,
+            false => 1,
+        };
+        self.v = self.v.wrapping_add(step) & VRAM_ADDR_MASK;
+    }
+
+    // Takes the NMI raised at the start of VBlank, if there is one
+    pub fn take_nmi(&mut self) -> bool {
+        let nmi = self.nmi_pending;
+        self.nmi_pending = false;
+        return nmi;
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        return self.mask.intersects(Mask::ShowBackground | Mask::ShowSprites);
+    }
+
+    pub fn tick(&mut self, dots: u64) {
+        for _ in 0..dots {
+            self.tick_dot();
+        }
+    }
+
+    // Scanlines are drawn whole at dot 256, where the hardware finishes
+    // fetching them, with the scroll v holds at that point. Writes made
+    // mid scanline show up from the next one.
+    fn tick_dot(&mut self) {
+        let rendering = self.rendering_enabled();
+
+        match (self.scanline, self.dot) {
+            (0 ..= 239, 256) => {
+                self.render_scanline();
+                if rendering {
+                    self.increment_y();
+                }
+            }
+            (VBLANK_SCANLINE, 1) => {
+                self.status.insert(Status::VBlank);
+                self.frames += 1;
+                if self.ctrl.contains(Control::GenerateNmi) {
+                    self.nmi_pending = true;
+                }
+            }
+            (PRE_RENDER_SCANLINE, 1) => {
+                self.status.remove(Status::VBlank | Status::SpriteZeroHit | Status::SpriteOverflow);
+            }
+            (PRE_RENDER_SCANLINE, 256) if rendering => self.increment_y(),
+            (PRE_RENDER_SCANLINE, 280 ..= 304) if rendering => {
+                self.v = (self.v & !VERTICAL_BITS) | (self.t & VERTICAL_BITS);
+            }
+            _ => {}
+        }
+
+        if (self.scanline < 240 || self.scanline == PRE_RENDER_SCANLINE)
+            && self.dot == 257 && rendering {
+            self.v = (self.v & !HORIZONTAL_BITS) | (self.t & HORIZONTAL_BITS);
+        }
+
+        let a12 = self.a12_level(rendering);
+        self.drive_a12(a12);
+
+        self.dot += 1;
+
+        // Odd frames skip the last dot of the pre-render line while rendering
+        if self.scanline == PRE_RENDER_SCANLINE && self.dot == DOTS_PER_SCANLINE - 1
+            && self.frames % 2 == 1 && rendering {
+            self.dot += 1;
+        }
+
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == SCANLINES_PER_FRAME {
+                self.scanline = 0;
+            }
+        }
+    }
+
+    // A12 as the fetches of the current dot would drive it. Each 8 dot
+    // fetch takes 4 dots on the nametable and attributes (A12 low) and 4
+    // on the pattern table. Outside of rendering the bus holds v.
+    fn a12_level(&self, rendering: bool) -> bool {
+        if !rendering || (self.scanline >= 240 && self.scanline != PRE_RENDER_SCANLINE) {
+            return self.v & PATTERN_TABLE_A12 != 0;
+        }
+
+        let pattern_fetch = (self.dot.wrapping_sub(1)) % 8 >= 4;
+        return match self.dot {
+            1 ..= 256 | 321 ..= 336 => {
+                pattern_fetch && self.ctrl.contains(Control::BackgroundPatternAddr)
+            }
+            // 8x16 sprites pick the table per tile, unused slots fetch
+            // tile 0xFF from the upper one
+            257 ..= 320 => {
+                pattern_fetch && self.ctrl.intersects(Control::SpritePatternAddr | Control::SpriteSize)
+            }
+            _ => false,
+        };
+    }
+
+    // Tells the cartridge about filtered A12 rises
+    fn drive_a12(&mut self, high: bool) {
+        if high && !self.a12 && self.a12_low_dots >= A12_FILTER_DOTS {
+            self.cartridge.borrow_mut().a12_rise();
+        }
+
+        self.a12_low_dots = match high {
+            true => 0,
+            false => self.a12_low_dots.saturating_add(1),
+        };
+        self.a12 = high;
+    }
+
+    fn render_scanline(&mut self) {
+        let y = self.scanline as usize;
+        let (origin_x, origin_y) = render::scroll_origin(self.v, self.fine_x);
+        let (line, flags) = render::render_scanline(self, y, origin_x, origin_y);
+        self.status.insert(flags);
+
+        for (x, &index) in line.iter().enumerate() {
+            let rgb = render::color(self, index);
+            self.frame.set_pixel(x, y, rgb);
+        }
+    }
+
+    // Moves v down a pixel, wrapping coarse Y at the bottom of the
+    // nametable into the one below it
+    fn increment_y(&mut self) {
+        if self.v & FINE_Y != FINE_Y {
+            self.v += 1 << 12;
+            return;
+        }
+
+        self.v &= !FINE_Y;
+        let mut coarse_y = (self.v & COARSE_Y) >> 5;
+        match coarse_y {
+            29 => {
+                coarse_y = 0;
+                self.v ^= NAMETABLE_Y;
+            }
+            // Out of bounds, wraps without switching nametable
+            31 => coarse_y = 0,
+            _ => coarse_y += 1,
+        }
+        self.v = (self.v & !COARSE_Y) | (coarse_y << 5);
+    }
+
+    // Selected by the cartridge, some switch it at runtime
+    pub fn mirroring(&self) -> Mirroring {
+        return self.cartridge.borrow().mirroring();
+    }
+
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        let addr = addr & VRAM_ADDR_MASK;
+        return match addr {
+            0 ..= PATTERN_TABLES_END => self.cartridge.borrow().ppu_read(addr),
+            NAMETABLES ..= NAMETABLES_MIRRORS_END => self.vram[self.mirroring().nametable_addr(addr)],
+            PALETTES ..= PALETTES_MIRRORS_END => self.palette[palette_index(addr)],
+            _ => 0,
+        };
+    }
+
+    pub fn write_vram(&mut self, addr: u16, data: u8) {
+        let addr = addr & VRAM_ADDR_MASK;
+        match addr {
+            0 ..= PATTERN_TABLES_END => self.cartridge.borrow_mut().ppu_write(addr, data),
+            NAMETABLES ..= NAMETABLES_MIRRORS_END => {
+                let index = self.mirroring().nametable_addr(addr);
+                self.vram[index] = data;
+            }
+            PALETTES ..= PALETTES_MIRRORS_END => self.palette[palette_index(addr)] = data,
+            _ => {}
+        }
+    }
+}
+
+// 0x3F10, 0x3F14, 0x3F18 and 0x3F1C are mirrors of the backdrop entries
+// 0x3F00, 0x3F04, 0x3F08 and 0x3F0C
+fn palette_index(addr: u16) -> usize {
+    let index = addr & 0x1F;
+    return match index & 0x13 {
+        0x10 => (index & 0x0F) as usize,
+        _ => index as usize,
+    };
+}
+
+bitflags! {
+    // PPUCTRL 0x2000, VPHB SINN
+    #[derive(PartialEq, Eq)]
+    #[derive(Clone, Copy)]
+    pub struct Control: u8 {
+        const Nametable1 = 0b0000_0001;
+        const Nametable2 = 0b0000_0010;
+        // Add 32 instead of 1 to the VRAM address per PPUDATA access
+        const VramIncrement = 0b0000_0100;
+        const SpritePatternAddr = 0b0000_1000;
+        const BackgroundPatternAddr = 0b0001_0000;
+        const SpriteSize = 0b0010_0000;
+        const MasterSlave = 0b0100_0000;
+        const GenerateNmi = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    // PPUMASK 0x2001, BGRs bMmG
+    #[derive(PartialEq, Eq)]
+    #[derive(Clone, Copy)]
+    pub struct Mask: u8 {
+        const Greyscale = 0b0000_0001;
+        const ShowBackgroundLeft = 0b0000_0010;
+        const ShowSpritesLeft = 0b0000_0100;
+        const ShowBackground = 0b0000_1000;
+        const ShowSprites = 0b0001_0000;
+        const EmphasizeRed = 0b0010_0000;
+        const EmphasizeGreen = 0b0100_0000;
+        const EmphasizeBlue = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    // PPUSTATUS 0x2002, VSO- ----
+    #[derive(PartialEq, Eq)]
+    #[derive(Clone, Copy)]
+    pub struct Status: u8 {
+        const SpriteOverflow = 0b0010_0000;
+        const SpriteZeroHit = 0b0100_0000;
+        const VBlank = 0b1000_0000;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::mapper::Nrom;
+
+    fn set_vram_addr(ppu: &mut PPU, addr: u16) {
+        ppu.write_register(PPUADDR, (addr >> 8) as u8);
+        ppu.write_register(PPUADDR, addr as u8);
+    }
+
+    #[test]
+    fn test_ppudata_read_buffer() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_vram(0x2305, 0x66);
+        ppu.write_vram(0x2306, 0x77);
+
+        set_vram_addr(&mut ppu, 0x2305);
+
+        // First read returns the stale buffer
+        assert_eq!(ppu.read_register(PPUDATA), 0x00);
+        assert_eq!(ppu.read_register(PPUDATA), 0x66);
+        assert_eq!(ppu.read_register(PPUDATA), 0x77);
+    }
+
+    #[test]
+    fn test_ppudata_palette_reads_are_not_buffered() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_vram(0x2F00, 0x11);
+        ppu.write_vram(0x3F00, 0x22);
+
+        set_vram_addr(&mut ppu, 0x3F00);
+
+        assert_eq!(ppu.read_register(PPUDATA), 0x22);
+        // Buffer picked up the nametable byte under the palette
+        set_vram_addr(&mut ppu, 0x2000);
+        assert_eq!(ppu.read_register(PPUDATA), 0x11);
+    }
+
+    #[test]
+    fn test_vram_increment() {
+        let mut ppu = PPU::new(vec![], Mirroring::Horizontal);
+        ppu.write_register(PPUCTRL, Control::VramIncrement.bits());
+        set_vram_addr(&mut ppu, 0x2000);
+
+        ppu.write_register(PPUDATA, 0x01);
+        ppu.write_register(PPUDATA, 0x02);
+
+        assert_eq!(ppu.v, 0x2040);
+        assert_eq!(ppu.read_vram(0x2000), 0x01);
+        assert_eq!(ppu.read_vram(0x2020), 0x02);
+
+        ppu.write_register(PPUCTRL, 0);
+        ppu.write_register(PPUDATA, 0x03);
+        assert_eq!(ppu.v, 0x2041);
+    }
+
+    #[test]
+    fn test_status_read_clears_vblank_and_latch() {
+        let mut ppu = PPU::new(vec![], Mirroring::Horizontal);
+        ppu.status.insert(Status::VBlank);
+
+        // Leave the latch halfway through an address
+        ppu.write_register(PPUADDR, 0x21);
+        assert_eq!(ppu.read_register(PPUSTATUS) & 0x80, 0x80);
+        assert_eq!(ppu.read_register(PPUSTATUS) & 0x80, 0);
+
+        set_vram_addr(&mut ppu, 0x2345);
+        assert_eq!(ppu.v, 0x2345);
+    }
+
+    #[test]
+    fn test_oam_and_palette_mirrors() {
+        let mut ppu = PPU::new(vec![], Mirroring::Horizontal);
+        ppu.write_register(OAMADDR, 0xFF);
+        ppu.write_register(OAMDATA, 0x12);
+        ppu.write_register(OAMDATA, 0x34);
+
+        assert_eq!(ppu.oam[0xFF], 0x12);
+        assert_eq!(ppu.oam[0x00], 0x34);
+
+        ppu.write_vram(0x3F10, 0x0F);
+        ppu.write_vram(0x3F25, 0x2A);
+        assert_eq!(ppu.read_vram(0x3F00), 0x0F);
+        assert_eq!(ppu.read_vram(0x3F05), 0x2A);
+    }
+
+    #[test]
+    fn test_mirroring_switched_at_runtime() {
+        let nrom = Rc::new(RefCell::new(Nrom::new(vec![], vec![], Mirroring::Vertical)));
+        let mut ppu = PPU::with_cartridge(nrom.clone());
+        ppu.write_vram(0x2000, 0x01);
+        assert_eq!(ppu.read_vram(0x2800), 0x01);
+        assert_eq!(ppu.read_vram(0x2400), 0x00);
+
+        nrom.borrow_mut().mirroring = Mirroring::SingleScreenLower;
+        assert_eq!(ppu.read_vram(0x2C00), 0x01);
+    }
+
+    #[test]
+    fn test_chr_rom_is_read_only() {
+        let mut rom = PPU::new(vec![0xAA; 0x2000], Mirroring::Vertical);
+        rom.write_vram(0x0010, 0x55);
+        assert_eq!(rom.read_vram(0x0010), 0xAA);
+
+        let mut ram = PPU::new(vec![], Mirroring::Vertical);
+        ram.write_vram(0x0010, 0x55);
+        assert_eq!(ram.read_vram(0x0010), 0x55);
+    }
+
+    #[test]
+    fn test_loopy_registers() {
+        let mut ppu = PPU::new(vec![], Mirroring::Horizontal);
+
+        ppu.write_register(PPUCTRL, 0b10);
+        assert_eq!(ppu.t, 0b000_10_00000_00000);
+
+        ppu.read_register(PPUSTATUS);
+        ppu.write_register(PPUSCROLL, 0b01111_101);
+        assert_eq!(ppu.t, 0b000_10_00000_01111);
+        assert_eq!(ppu.fine_x, 0b101);
+
+        ppu.write_register(PPUSCROLL, 0b01011_110);
+        assert_eq!(ppu.t, 0b110_10_01011_01111);
+
+        // PPUADDR clears bit 14 then copies t into v on the second write
+        ppu.write_register(PPUADDR, 0b00_111101);
+        assert_eq!(ppu.t, 0b011_11_01011_01111);
+        assert_eq!(ppu.v, 0);
+        ppu.write_register(PPUADDR, 0b11110000);
+        assert_eq!(ppu.t, 0b011_11_01111_10000);
+        assert_eq!(ppu.v, ppu.t);
+    }
+
+    #[test]
+    fn test_vblank_and_nmi_timing() {
+        let mut ppu = PPU::new(vec![], Mirroring::Horizontal);
+        ppu.write_register(PPUCTRL, Control::GenerateNmi.bits());
+
+        // Up to dot 0 of scanline 241
+        ppu.tick(241 * DOTS_PER_SCANLINE as u64 + 1);
+        assert!(!ppu.status.contains(Status::VBlank));
+        assert!(!ppu.take_nmi());
+
+        ppu.tick(1);
+        assert!(ppu.status.contains(Status::VBlank));
+        assert_eq!(ppu.frames, 1);
+        assert!(ppu.take_nmi());
+        assert!(!ppu.take_nmi());
+
+        // Cleared on the pre-render line
+        ppu.tick(20 * DOTS_PER_SCANLINE as u64);
+        assert_eq!((ppu.scanline, ppu.dot), (261, 2));
+        assert!(!ppu.status.contains(Status::VBlank));
+    }
+
+    #[test]
+    fn test_enabling_nmi_during_vblank() {
+        let mut ppu = PPU::new(vec![], Mirroring::Horizontal);
+        ppu.tick(241 * DOTS_PER_SCANLINE as u64 + 2);
+        assert!(!ppu.take_nmi());
+
+        ppu.write_register(PPUCTRL, Control::GenerateNmi.bits());
+        assert!(ppu.take_nmi());
+        // Rewriting it while already enabled doesn't fire again
+        ppu.write_register(PPUCTRL, Control::GenerateNmi.bits());
+        assert!(!ppu.take_nmi());
+    }
+
+    #[test]
+    fn test_mid_frame_scroll_split() {
+        // Tile 1 is solid
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF);
+        let mut ppu = PPU::new(chr, Mirroring::Vertical);
+        ppu.write_vram(0x3F01, 0x30);
+        // Tile row 15 starts at scanline 120, left nametable has tile 1 in
+        // column 0 and the right one in column 1
+        ppu.write_vram(0x2000 + 15 * 32, 0x01);
+        ppu.write_vram(0x2400 + 15 * 32 + 1, 0x01);
+        ppu.write_register(PPUMASK, (Mask::ShowBackground | Mask::ShowBackgroundLeft).bits());
+
+        // Frame starts from the pre-render line copying t into v, run up
+        // to the start of scanline 120
+        ppu.scanline = 261;
+        ppu.tick(DOTS_PER_SCANLINE as u64 + 120 * DOTS_PER_SCANLINE as u64);
+
+        // Switch to the right nametable, horizontal scroll only reaches v
+        // once scanline 120 has been drawn
+        ppu.write_register(PPUCTRL, 0b01);
+        ppu.tick(2 * DOTS_PER_SCANLINE as u64);
+
+        let white = crate::palette::SYSTEM_PALETTE[0x30];
+        assert_eq!(ppu.frame.pixel(0, 120), white);
+        assert_ne!(ppu.frame.pixel(8, 120), white);
+        assert_ne!(ppu.frame.pixel(0, 121), white);
+        assert_eq!(ppu.frame.pixel(8, 121), white);
+    }
+
+    // Counts A12 rises, otherwise an NROM board with CHR RAM
+    struct A12Counter {
+        nrom: Nrom,
+        rises: u32,
+    }
+
+    impl crate::mapper::Mapper for A12Counter {
+        fn cpu_peek(&self, addr: u16) -> u8 {
+            return self.nrom.cpu_peek(addr);
+        }
+
+        fn cpu_write(&mut self, addr: u16, data: u8) {
+            self.nrom.cpu_write(addr, data);
+        }
+
+        fn ppu_read(&self, addr: u16) -> u8 {
+            return self.nrom.ppu_read(addr);
+        }
+
+        fn ppu_write(&mut self, addr: u16, data: u8) {
+            self.nrom.ppu_write(addr, data);
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            return self.nrom.mirroring();
+        }
+
+        fn a12_rise(&mut self) {
+            self.rises += 1;
+        }
+    }
+
+    fn count_a12_rises(ctrl: Control, mask: Mask) -> u32 {
+        let counter = Rc::new(RefCell::new(A12Counter {
+            nrom: Nrom::new(vec![], vec![], Mirroring::Vertical),
+            rises: 0,
+        }));
+        let mut ppu = PPU::with_cartridge(counter.clone());
+        ppu.write_register(PPUCTRL, ctrl.bits());
+        ppu.write_register(PPUMASK, mask.bits());
+
+        // A12 sits low from power on, so skip the first frame
+        let frame = DOTS_PER_SCANLINE as u64 * SCANLINES_PER_FRAME as u64;
+        ppu.tick(frame);
+        counter.borrow_mut().rises = 0;
+        ppu.tick(frame);
+        let rises = counter.borrow().rises;
+        return rises;
+    }
+
+    #[test]
+    fn test_a12_rises_once_per_scanline() {
+        let rendering = Mask::ShowBackground | Mask::ShowSprites;
+
+        // 240 visible lines and the pre-render line, whichever table is
+        // the upper one. The dips between background fetches are filtered.
+        assert_eq!(count_a12_rises(Control::SpritePatternAddr, rendering), 241);
+        assert_eq!(count_a12_rises(Control::SpriteSize, rendering), 241);
+
+        // A background in the upper table also rises on the first fetch
+        // of the pre-render line, after A12 sat low through vblank
+        assert_eq!(count_a12_rises(Control::BackgroundPatternAddr, rendering), 242);
+
+        // Both in the lower table, or not rendering
+        assert_eq!(count_a12_rises(Control::empty(), rendering), 0);
+        assert_eq!(count_a12_rises(Control::SpritePatternAddr, Mask::empty()), 0);
+    }
+}
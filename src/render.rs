@@ -0,0 +1,451 @@
+use crate::frame::Frame;
+use crate::ppu::{Control, Mask, Status, PPU};
+
+const NAMETABLES: u16 = 0x2000;
+const NAMETABLE_SIZE: u16 = 0x400;
+// The 4 nametables laid out 2x2
+const PLANE_WIDTH: usize = Frame::WIDTH * 2;
+const PLANE_HEIGHT: usize = Frame::HEIGHT * 2;
+// Attribute table sits after the 32x30 tiles of every nametable
+const ATTRIBUTE_TABLE: u16 = 0x3C0;
+const PALETTES: u16 = 0x3F00;
+
+const SPRITE_PALETTES: u8 = 0x10;
+
+// Bytes per 8x8 tile in the pattern tables, 8 for each bit plane
+const TILE_SIZE: u16 = 16;
+
+// OAM holds 64 sprites of 4 bytes: Y, tile, attributes, X
+const SPRITE_COUNT: usize = 64;
+const SPRITES_PER_SCANLINE: usize = 8;
+const ATTRIBUTE_FLIP_VERTICAL: u8 = 0b1000_0000;
+const ATTRIBUTE_FLIP_HORIZONTAL: u8 = 0b0100_0000;
+const ATTRIBUTE_BEHIND_BACKGROUND: u8 = 0b0010_0000;
+const ATTRIBUTE_PALETTE: u8 = 0b0000_0011;
+
+// A sprite's opaque pixel at some position on the screen
+struct SpritePixel {
+    // Palette RAM index
+    index: u8,
+    behind_background: bool,
+    sprite_zero: bool,
+}
+
+// Draws the whole picture with the scroll PPUSCROLL / PPUCTRL have set up,
+// as if nothing changed while the frame was drawn. The PPU draws per dot,
+// this is what the tests check scanlines against.
+#[cfg(test)]
+pub fn render(ppu: &mut PPU, frame: &mut Frame) {
+    // Cleared on the pre-render scanline
+    ppu.status.remove(Status::SpriteZeroHit | Status::SpriteOverflow);
+
+    let (origin_x, origin_y) = scroll_origin(ppu.t, ppu.fine_x);
+
+    for y in 0..Frame::HEIGHT {
+        let (line, flags) = render_scanline(ppu, y, origin_x, (origin_y + y) % PLANE_HEIGHT);
+        ppu.status.insert(flags);
+
+        for (x, &index) in line.iter().enumerate() {
+            frame.set_pixel(x, y, color(ppu, index));
+        }
+    }
+}
+
+// Position in the 512x480 plane made of the 4 nametables of a loopy v / t
+// address and fine X scroll
+pub fn scroll_origin(addr: u16, fine_x: u8) -> (usize, usize) {
+    let coarse_x = (addr & 0x1F) as usize;
+    let coarse_y = ((addr >> 5) & 0x1F) as usize;
+    let nametable = ((addr >> 10) & 0b11) as usize;
+    let fine_y = ((addr >> 12) & 0b111) as usize;
+
+    let x = (nametable & 1) * Frame::WIDTH + coarse_x * 8 + fine_x as usize;
+    let y = (nametable >> 1) * Frame::HEIGHT + coarse_y * 8 + fine_y;
+
+    return (x % PLANE_WIDTH, y % PLANE_HEIGHT);
+}
+
+// Palette RAM indices of scanline y with its left most pixel at
+// origin_x, origin_y in the nametable plane, along with the sprite
+// overflow and sprite 0 hit flags it raised
+pub fn render_scanline(
+    ppu: &PPU, y: usize, origin_x: usize, origin_y: usize
+) -> ([u8; Frame::WIDTH], Status) {
+    let mut line = [0; Frame::WIDTH];
+    let mut flags = Status::empty();
+
+    let mut sprites = evaluate_sprites(ppu, y);
+    if sprites.len() > SPRITES_PER_SCANLINE {
+        flags.insert(Status::SpriteOverflow);
+        sprites.truncate(SPRITES_PER_SCANLINE);
+    }
+
+    for x in 0..Frame::WIDTH {
+        let background = background_pixel(ppu, x, (origin_x + x) % PLANE_WIDTH, origin_y);
+        let background_opaque = background & 0b11 != 0;
+
+        line[x] = match sprite_pixel(ppu, &sprites, x, y) {
+            Some(sprite) => {
+                // Never hits on the last pixel of the line
+                if sprite.sprite_zero && background_opaque && x != Frame::WIDTH - 1 {
+                    flags.insert(Status::SpriteZeroHit);
+                }
+
+                match sprite.behind_background && background_opaque {
+                    true => background,
+                    false => sprite.index,
+                }
+            }
+            None => background,
+        };
+    }
+
+    return (line, flags);
+}
+
+pub fn sprite_height(ppu: &PPU) -> usize {
+    return match ppu.ctrl.contains(Control::SpriteSize) {
+        true => 16,
+        false => 8,
+    };
+}
+
+// OAM indices of the sprites on scanline y, stopping at the 9th. More
+// than 8 means overflow, without the hardware's false positives and
+// negatives.
+pub fn evaluate_sprites(ppu: &PPU, y: usize) -> Vec<usize> {
+    let height = sprite_height(ppu);
+    let mut sprites = vec![];
+
+    for sprite in 0..SPRITE_COUNT {
+        // Sprites are drawn one scanline below their Y
+        let top = ppu.oam[sprite * 4] as usize + 1;
+        if y < top || y >= top + height {
+            continue;
+        }
+
+        sprites.push(sprite);
+        if sprites.len() > SPRITES_PER_SCANLINE {
+            break;
+        }
+    }
+
+    return sprites;
+}
+
+// First opaque pixel at x, y among sprites, lower OAM indices in front.
+// Sprites behind the background still hide later sprites.
+fn sprite_pixel(ppu: &PPU, sprites: &[usize], x: usize, y: usize) -> Option<SpritePixel> {
+    if !ppu.mask.contains(Mask::ShowSprites) {
+        return None;
+    }
+    if x < 8 && !ppu.mask.contains(Mask::ShowSpritesLeft) {
+        return None;
+    }
+
+    let height = sprite_height(ppu);
+
+    for &sprite in sprites {
+        let top = ppu.oam[sprite * 4] as usize + 1;
+        let tile = ppu.oam[sprite * 4 + 1] as u16;
+        let attributes = ppu.oam[sprite * 4 + 2];
+        let left = ppu.oam[sprite * 4 + 3] as usize;
+
+        if x < left || x >= left + 8 {
+            continue;
+        }
+
+        let mut column = x - left;
+        if attributes & ATTRIBUTE_FLIP_HORIZONTAL != 0 {
+            column = 7 - column;
+        }
+
+        let mut row = y - top;
+        if attributes & ATTRIBUTE_FLIP_VERTICAL != 0 {
+            row = height - 1 - row;
+        }
+
+        // 8x16 sprites pick their bank with bit 0 of the tile, the top
+        // half is the even tile and the bottom half the one after it
+        let addr = match height {
+            16 => {
+                let bank = (tile & 1) * 0x1000;
+                let tile = (tile & 0xFE) + (row / 8) as u16;
+                bank + tile * TILE_SIZE
+            }
+            _ => {
+                let bank: u16 = match ppu.ctrl.contains(Control::SpritePatternAddr) {
+                    true => 0x1000,
+                    false => 0,
+                };
+                bank + tile * TILE_SIZE
+            }
+        };
+
+        let value = pattern_pixel(ppu, addr, column, row % 8);
+        if value == 0 {
+            continue;
+        }
+
+        return Some(SpritePixel {
+            index: SPRITE_PALETTES | (attributes & ATTRIBUTE_PALETTE) << 2 | value,
+            behind_background: attributes & ATTRIBUTE_BEHIND_BACKGROUND != 0,
+            sprite_zero: sprite == 0,
+        });
+    }
+
+    return None;
+}
+
+// Palette RAM index of the background at plane_x, plane_y in the nametable
+// plane, drawn at x on the screen. The low 2 bits are 0 where the
+// background is transparent or hidden.
+pub fn background_pixel(ppu: &PPU, x: usize, plane_x: usize, plane_y: usize) -> u8 {
+    if !ppu.mask.contains(Mask::ShowBackground) {
+        return 0;
+    }
+    if x < 8 && !ppu.mask.contains(Mask::ShowBackgroundLeft) {
+        return 0;
+    }
+
+    let table = (plane_x / Frame::WIDTH + (plane_y / Frame::HEIGHT) * 2) as u16;
+    let nametable = NAMETABLES + table * NAMETABLE_SIZE;
+
+    let x = plane_x % Frame::WIDTH;
+    let y = plane_y % Frame::HEIGHT;
+    let tile_x = (x / 8) as u16;
+    let tile_y = (y / 8) as u16;
+
+    let tile = ppu.read_vram(nametable + tile_y * 32 + tile_x) as u16;
+
+    // Each attribute byte covers 4x4 tiles, 2 bits for each 2x2 quadrant
+    let attribute = ppu.read_vram(nametable + ATTRIBUTE_TABLE + (tile_y / 4) * 8 + tile_x / 4);
+    let shift = ((tile_y % 4) / 2) * 4 + ((tile_x % 4) / 2) * 2;
+    let palette = (attribute >> shift) & 0b11;
+
+    let bank: u16 = match ppu.ctrl.contains(Control::BackgroundPatternAddr) {
+        true => 0x1000,
+        false => 0,
+    };
+    let value = pattern_pixel(ppu, bank + tile * TILE_SIZE, x % 8, y % 8);
+
+    return palette << 2 | value;
+}
+
+// 2 bit colour of pixel x, y inside the tile at addr in the pattern tables
+pub fn pattern_pixel(ppu: &PPU, addr: u16, x: usize, y: usize) -> u8 {
+    let lo = ppu.read_vram(addr + y as u16);
+    let hi = ppu.read_vram(addr + y as u16 + 8);
+    let bit = 7 - x;
+
+    return ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1);
+}
+
+// RGB of a palette RAM index, transparent pixels show the backdrop.
+// Greyscale and emphasis come from PPUMASK as it is when the line is drawn.
+pub fn color(ppu: &PPU, index: u8) -> (u8, u8, u8) {
+    let index = match index & 0b11 {
+        0 => 0,
+        _ => index,
+    };
+    let color = ppu.read_vram(PALETTES + index as u16);
+
+    return ppu.system_palette.color(color, ppu.mask);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::palette::SYSTEM_PALETTE;
+    use crate::ppu::{PPUCTRL, PPUSCROLL};
+
+    // Tile 1 is solid colour 1 on its top row and colour 3 below it
+    fn test_ppu() -> PPU {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10] = 0xFF;
+        for row in 1..8 {
+            chr[0x10 + row] = 0xFF;
+            chr[0x18 + row] = 0xFF;
+        }
+
+        let mut ppu = PPU::new(chr, Mirroring::Vertical);
+        ppu.mask = Mask::ShowBackground | Mask::ShowBackgroundLeft;
+        ppu.write_vram(0x3F00, 0x0F);
+        ppu.write_vram(0x3F01, 0x01);
+        ppu.write_vram(0x3F03, 0x03);
+        ppu.write_vram(0x3F0D, 0x2D);
+        ppu.write_vram(0x3F0F, 0x30);
+        ppu.write_vram(0x3F11, 0x11);
+        ppu.write_vram(0x3F13, 0x13);
+        ppu.write_vram(0x3F15, 0x15);
+        ppu.write_vram(0x3F17, 0x17);
+        // Sprites start hidden below the screen
+        ppu.oam = [0xFF; 256];
+        return ppu;
+    }
+
+    fn set_sprite(ppu: &mut PPU, sprite: usize, x: u8, y: u8, tile: u8, attributes: u8) {
+        ppu.oam[sprite * 4] = y;
+        ppu.oam[sprite * 4 + 1] = tile;
+        ppu.oam[sprite * 4 + 2] = attributes;
+        ppu.oam[sprite * 4 + 3] = x;
+    }
+
+    #[test]
+    fn test_render_background_tile() {
+        let mut ppu = test_ppu();
+        // Tile 1 at the top left, then at tile 2,0 using palette 3
+        ppu.write_vram(0x2000, 0x01);
+        ppu.write_vram(0x2002, 0x01);
+        ppu.write_vram(0x23C0, 0b0000_1100);
+
+        let mut frame = Frame::new();
+        render(&mut ppu, &mut frame);
+
+        assert_eq!(frame.pixel(0, 0), SYSTEM_PALETTE[0x01]);
+        assert_eq!(frame.pixel(7, 1), SYSTEM_PALETTE[0x03]);
+        assert_eq!(frame.pixel(8, 0), SYSTEM_PALETTE[0x0F]);
+        assert_eq!(frame.pixel(16, 0), SYSTEM_PALETTE[0x2D]);
+        assert_eq!(frame.pixel(16, 1), SYSTEM_PALETTE[0x30]);
+    }
+
+    #[test]
+    fn test_render_scrolled_into_next_nametable() {
+        let mut ppu = test_ppu();
+        // First tile of the nametable to the right
+        ppu.write_vram(0x2400, 0x01);
+        ppu.write_register(PPUSCROLL, 8);
+        ppu.write_register(PPUSCROLL, 0);
+
+        let mut frame = Frame::new();
+        render(&mut ppu, &mut frame);
+
+        assert_eq!(frame.pixel(248, 1), SYSTEM_PALETTE[0x03]);
+        assert_eq!(frame.pixel(247, 1), SYSTEM_PALETTE[0x0F]);
+
+        // Selecting it through PPUCTRL instead of scrolling
+        ppu.write_register(PPUSCROLL, 0);
+        ppu.write_register(PPUSCROLL, 0);
+        ppu.write_register(PPUCTRL, Control::Nametable1.bits());
+        render(&mut ppu, &mut frame);
+        assert_eq!(frame.pixel(0, 1), SYSTEM_PALETTE[0x03]);
+    }
+
+    #[test]
+    fn test_hidden_background_shows_backdrop() {
+        let mut ppu = test_ppu();
+        ppu.write_vram(0x2000, 0x01);
+        ppu.write_vram(0x2001, 0x01);
+        ppu.mask = Mask::ShowBackground;
+
+        let mut frame = Frame::new();
+        render(&mut ppu, &mut frame);
+
+        // Left 8 pixels are clipped
+        assert_eq!(frame.pixel(0, 1), SYSTEM_PALETTE[0x0F]);
+        assert_eq!(frame.pixel(8, 1), SYSTEM_PALETTE[0x03]);
+    }
+
+    #[test]
+    fn test_render_sprite_with_flipping() {
+        let mut ppu = test_ppu();
+        ppu.mask.insert(Mask::ShowSprites | Mask::ShowSpritesLeft);
+        // Tile 1 as is, then flipped vertically using palette 1
+        set_sprite(&mut ppu, 0, 16, 9, 0x01, 0);
+        set_sprite(&mut ppu, 1, 32, 9, 0x01, ATTRIBUTE_FLIP_VERTICAL | 1);
+
+        let mut frame = Frame::new();
+        render(&mut ppu, &mut frame);
+
+        // Drawn from the scanline after their Y
+        assert_eq!(frame.pixel(16, 9), SYSTEM_PALETTE[0x0F]);
+        assert_eq!(frame.pixel(16, 10), SYSTEM_PALETTE[0x11]);
+        assert_eq!(frame.pixel(23, 11), SYSTEM_PALETTE[0x13]);
+        assert_eq!(frame.pixel(32, 10), SYSTEM_PALETTE[0x17]);
+        assert_eq!(frame.pixel(32, 17), SYSTEM_PALETTE[0x15]);
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let mut ppu = test_ppu();
+        ppu.mask.insert(Mask::ShowSprites | Mask::ShowSpritesLeft);
+        ppu.write_vram(0x2000, 0x01);
+        // Behind the background, only shows through transparent pixels
+        set_sprite(&mut ppu, 0, 4, 1, 0x01, ATTRIBUTE_BEHIND_BACKGROUND);
+        // Lower OAM index wins even when it is behind the background
+        set_sprite(&mut ppu, 1, 4, 1, 0x01, 1);
+
+        let mut frame = Frame::new();
+        render(&mut ppu, &mut frame);
+
+        assert_eq!(frame.pixel(4, 3), SYSTEM_PALETTE[0x03]);
+        assert_eq!(frame.pixel(8, 3), SYSTEM_PALETTE[0x13]);
+    }
+
+    #[test]
+    fn test_8x16_sprites() {
+        let mut ppu = test_ppu();
+        ppu.mask.insert(Mask::ShowSprites | Mask::ShowSpritesLeft);
+        ppu.ctrl = Control::SpriteSize;
+        // Tile 0 (empty) on top of tile 1 from the 0x0000 bank
+        set_sprite(&mut ppu, 0, 0, 0, 0x00, 0);
+        set_sprite(&mut ppu, 1, 8, 0, 0x00, ATTRIBUTE_FLIP_VERTICAL);
+
+        let mut frame = Frame::new();
+        render(&mut ppu, &mut frame);
+
+        assert_eq!(frame.pixel(0, 8), SYSTEM_PALETTE[0x0F]);
+        assert_eq!(frame.pixel(0, 9), SYSTEM_PALETTE[0x11]);
+        assert_eq!(frame.pixel(0, 10), SYSTEM_PALETTE[0x13]);
+        // Flipped, tile 1 ends up on top with its first row last
+        assert_eq!(frame.pixel(8, 1), SYSTEM_PALETTE[0x13]);
+        assert_eq!(frame.pixel(8, 8), SYSTEM_PALETTE[0x11]);
+        assert_eq!(frame.pixel(8, 9), SYSTEM_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let mut ppu = test_ppu();
+        ppu.mask.insert(Mask::ShowSprites | Mask::ShowSpritesLeft);
+        for sprite in 0..9 {
+            set_sprite(&mut ppu, sprite, (sprite * 8) as u8, 20, 0x01, 0);
+        }
+
+        let mut frame = Frame::new();
+        render(&mut ppu, &mut frame);
+
+        assert!(ppu.status.contains(Status::SpriteOverflow));
+        // The 9th sprite isn't drawn
+        assert_eq!(frame.pixel(56, 22), SYSTEM_PALETTE[0x13]);
+        assert_eq!(frame.pixel(64, 22), SYSTEM_PALETTE[0x0F]);
+
+        set_sprite(&mut ppu, 8, 64, 40, 0x01, 0);
+        render(&mut ppu, &mut frame);
+        assert!(!ppu.status.contains(Status::SpriteOverflow));
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut ppu = test_ppu();
+        ppu.mask.insert(Mask::ShowSprites | Mask::ShowSpritesLeft);
+        ppu.write_vram(0x2021, 0x01);
+
+        // Overlaps transparent background only
+        set_sprite(&mut ppu, 0, 40, 7, 0x01, 0);
+        let mut frame = Frame::new();
+        render(&mut ppu, &mut frame);
+        assert!(!ppu.status.contains(Status::SpriteZeroHit));
+
+        // Opaque on opaque, even behind the background
+        set_sprite(&mut ppu, 0, 4, 7, 0x01, ATTRIBUTE_BEHIND_BACKGROUND);
+        render(&mut ppu, &mut frame);
+        assert!(ppu.status.contains(Status::SpriteZeroHit));
+
+        // Other sprites don't count
+        set_sprite(&mut ppu, 0, 40, 7, 0x01, 0);
+        set_sprite(&mut ppu, 1, 4, 7, 0x01, 0);
+        render(&mut ppu, &mut frame);
+        assert!(!ppu.status.contains(Status::SpriteZeroHit));
+    }
+}
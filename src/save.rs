@@ -0,0 +1,187 @@
+use std::{fmt, fs, io, path::{Path, PathBuf}, time::{Duration, Instant}};
+
+use crate::mapper::Mapper;
+
+// How often battery RAM is written out while the game keeps changing it
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum SaveError {
+    // File isn't the size of the cartridge's battery RAM
+    SizeMismatch { expected: usize, actual: usize },
+    Io(io::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::SizeMismatch { expected, actual } => write!(
+                f, "save is {} bytes, expected {}", actual, expected),
+            SaveError::Io(err) => write!(f, "failed to access save: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+/// Battery backed PRG RAM kept in a .sav file. It is written out when it
+/// changed, at most every few seconds and once more on exit.
+pub struct SaveFile {
+    path: PathBuf,
+    // Contents as last read or written
+    saved: Vec<u8>,
+    last_flush: Instant,
+}
+
+impl SaveFile {
+
+    // game.nes saves to game.sav
+    pub fn path_for<P: AsRef<Path>>(rom_path: P) -> PathBuf {
+        return rom_path.as_ref().with_extension("sav");
+    }
+
+    // Fills the cartridge's battery RAM from the file when there is one.
+    // None for cartridges without a battery.
+    pub fn open<P: AsRef<Path>>(path: P, mapper: &mut dyn Mapper) -> Result<Option<SaveFile>, SaveError> {
+        let ram = match mapper.battery_ram_mut() {
+            Some(ram) => ram,
+            None => return Ok(None),
+        };
+
+        match fs::read(&path) {
+            Ok(data) if data.len() != ram.len() => {
+                return Err(SaveError::SizeMismatch { expected: ram.len(), actual: data.len() });
+            }
+            Ok(data) => ram.copy_from_slice(&data),
+            // Nothing saved yet
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        return Ok(Some(SaveFile {
+            path: path.as_ref().to_path_buf(),
+            saved: ram.to_vec(),
+            last_flush: Instant::now(),
+        }));
+    }
+
+    // Writes the RAM out if it changed since it was last read or written
+    pub fn flush(&mut self, mapper: &dyn Mapper) -> Result<(), SaveError> {
+        self.last_flush = Instant::now();
+
+        let ram = match mapper.battery_ram() {
+            Some(ram) => ram,
+            None => return Ok(()),
+        };
+        if ram == self.saved.as_slice() {
+            return Ok(());
+        }
+
+        // Written alongside then renamed over, dying mid write leaves the
+        // previous save intact
+        let temp = self.path.with_extension("sav.tmp");
+        fs::write(&temp, ram)?;
+        fs::rename(&temp, &self.path)?;
+        self.saved = ram.to_vec();
+        return Ok(());
+    }
+
+    // Called every frame, flushes once FLUSH_INTERVAL has passed
+    pub fn tick(&mut self, mapper: &dyn Mapper) -> Result<(), SaveError> {
+        if self.last_flush.elapsed() < FLUSH_INTERVAL {
+            return Ok(());
+        }
+        return self.flush(mapper);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::RomBuilder;
+    use crate::mapper::PRG_RAM;
+    use crate::mmc1::Mmc1;
+
+    // SNROM style MMC1 board, 32 KiB PRG ROM and no CHR ROM so 8 KiB of
+    // CHR RAM
+    fn mmc1(battery: bool) -> Mmc1 {
+        return Mmc1::new(RomBuilder::new(1).battery(battery).prg_rom(vec![0; 32 * 1024]).build());
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        return std::env::temp_dir().join(format!("rgboy-{}-{}.sav", name, std::process::id()));
+    }
+
+    #[test]
+    fn test_path_for() {
+        assert_eq!(SaveFile::path_for("roms/zelda.nes"), PathBuf::from("roms/zelda.sav"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = temp_path("round-trip");
+        let _ = fs::remove_file(&path);
+
+        let mut mapper = mmc1(true);
+        let mut save = SaveFile::open(&path, &mut mapper).unwrap().unwrap();
+
+        // Untouched RAM isn't written
+        save.flush(&mapper).unwrap();
+        assert!(!path.exists());
+
+        mapper.cpu_write(PRG_RAM, 0x12);
+        mapper.cpu_write(PRG_RAM + 0x1FFF, 0x34);
+        save.flush(&mapper).unwrap();
+
+        // Powered back on
+        let mut mapper = mmc1(true);
+        SaveFile::open(&path, &mut mapper).unwrap().unwrap();
+        assert_eq!(mapper.cpu_peek(PRG_RAM), 0x12);
+        assert_eq!(mapper.cpu_peek(PRG_RAM + 0x1FFF), 0x34);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tick_waits_for_interval() {
+        let path = temp_path("tick");
+        let _ = fs::remove_file(&path);
+
+        let mut mapper = mmc1(true);
+        let mut save = SaveFile::open(&path, &mut mapper).unwrap().unwrap();
+        mapper.cpu_write(PRG_RAM, 0x56);
+
+        save.tick(&mapper).unwrap();
+        assert!(!path.exists());
+
+        save.last_flush -= FLUSH_INTERVAL;
+        save.tick(&mapper).unwrap();
+        assert_eq!(fs::read(&path).unwrap()[0], 0x56);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_without_battery() {
+        let mut mapper = mmc1(false);
+        assert!(SaveFile::open(temp_path("no-battery"), &mut mapper).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let path = temp_path("size-mismatch");
+        fs::write(&path, [0; 100]).unwrap();
+
+        let mut mapper = mmc1(true);
+        let result = SaveFile::open(&path, &mut mapper);
+        assert!(matches!(result, Err(SaveError::SizeMismatch { expected: 0x2000, actual: 100 })));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
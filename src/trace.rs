@@ -0,0 +1,202 @@
+use crate::cpu::{Memory, CPU};
+use crate::disasm::{self, Syntax};
+use crate::opcodes::{self, AddressingMode, Opcode};
+
+/// Formats the instruction at the CPU's counter the same way nestest.log
+/// does, must be called before the instruction executes e.g.
+///
+/// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+pub fn trace(cpu: &CPU) -> String {
+    let pc = cpu.counter;
+    let byte_code = cpu.mem_peek(pc);
+
+    let (len, asm) = match opcodes::lookup(byte_code) {
+        Some(opcode) => {
+            let star = match opcodes::is_illegal(byte_code) {
+                true => "*",
+                false => " ",
+            };
+            let operand = trace_operand(cpu, opcode);
+            (opcode.len, format!("{}{} {}", star, opcode.name, operand).trim_end().to_string())
+        }
+        None => (1, " ???".to_string()),
+    };
+
+    let bytes = (0..len as u16)
+        .map(|i| format!("{:02X}", cpu.mem_peek(pc.wrapping_add(i))))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    return format!(
+        "{:04X}  {:<8} {:<33}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        pc, bytes, asm,
+        cpu.register_a, cpu.register_x, cpu.register_y, cpu.status.bits(), cpu.stack_pointer,
+        cpu.bus.ppu.scanline, cpu.bus.ppu.dot, cpu.cycles);
+}
+
+// Operand with its resolved address and the value currently stored there
+fn trace_operand(cpu: &CPU, opcode: &Opcode) -> String {
+    use AddressingMode::*;
+
+    let mode = opcode.mode;
+    let pos = cpu.counter.wrapping_add(1);
+    let operand = cpu.mem_peek(pos);
+
+    let addr = match mode {
+        NONE_ADDRESSING => 0,
+        _ => cpu.get_absolute_addr(mode, pos).unwrap_or(0),
+    };
+    let value = match mode {
+        NONE_ADDRESSING | IMMEDIATE => 0,
+        _ => cpu.mem_peek(addr),
+    };
+
+    return match mode {
+        IMMEDIATE => format!("#${:02X}", operand),
+        ZERO_PAGE => format!("${:02X} = {:02X}", addr, value),
+        ZERO_PAGE_X => format!("${:02X},X @ {:02X} = {:02X}", operand, addr, value),
+        ZERO_PAGE_Y => format!("${:02X},Y @ {:02X} = {:02X}", operand, addr, value),
+        // Jumps don't read the address so there's no value to show
+        ABSOLUTE if opcode.name == "JMP" || opcode.name == "JSR" => format!("${:04X}", addr),
+        ABSOLUTE => format!("${:04X} = {:02X}", addr, value),
+        ABSOLUTE_X => format!(
+            "${:04X},X @ {:04X} = {:02X}", cpu.mem_peek_u16(pos), addr, value),
+        ABSOLUTE_Y => format!(
+            "${:04X},Y @ {:04X} = {:02X}", cpu.mem_peek_u16(pos), addr, value),
+        INDIRECT_X => format!(
+            "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+            operand, operand.wrapping_add(cpu.register_x), addr, value),
+        INDIRECT_Y => format!(
+            "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+            operand, addr.wrapping_sub(cpu.register_y as u16), addr, value),
+        NONE_ADDRESSING => match disasm::syntax(opcode) {
+            // Relative branches show their target
+            Syntax::Relative => {
+                let target = pos.wrapping_add(1).wrapping_add(operand as i8 as u16);
+                format!("${:04X}", target)
+            }
+            // JMP indirect, including the page boundary bug
+            Syntax::Indirect => {
+                let ptr = cpu.mem_peek_u16(pos);
+                let target = match ptr & 0x00FF {
+                    0x00FF => u16::from_le_bytes([cpu.mem_peek(ptr), cpu.mem_peek(ptr & 0xFF00)]),
+                    _ => cpu.mem_peek_u16(ptr),
+                };
+                format!("(${:04X}) = {:04X}", ptr, target)
+            }
+            Syntax::Accumulator => "A".to_string(),
+            _ => String::new(),
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::opcodes::{BRK, DEX, LDA, LDX, STA};
+
+    #[test]
+    fn test_format_trace() {
+        let mut cpu = CPU::new();
+        cpu.halt_on_brk = true;
+        cpu.mem_write(0x64, LDX::IMMEDIATE::VALUE);
+        cpu.mem_write(0x65, 0x01);
+        cpu.mem_write(0x66, DEX::NONE_ADDRESSING::VALUE);
+        cpu.mem_write(0x67, 0x80); // *NOP #$FF
+        cpu.mem_write(0x68, 0xFF);
+        cpu.mem_write(0x69, BRK::NONE_ADDRESSING::VALUE);
+
+        cpu.counter = 0x64;
+        // As if coming out of the 7 cycle reset
+        cpu.cycles = 7;
+        cpu.bus.tick(7);
+        cpu.register_a = 1;
+        cpu.register_x = 2;
+        cpu.register_y = 3;
+
+        let mut result: Vec<String> = vec![];
+        cpu.run_with_callback(|cpu| {
+            result.push(trace(cpu));
+        }).unwrap();
+
+        assert_eq!(
+            "0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD PPU:  0, 21 CYC:7",
+            result[0]
+        );
+        assert_eq!(
+            "0066  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD PPU:  0, 27 CYC:9",
+            result[1]
+        );
+        assert_eq!(
+            "0067  80 FF    *NOP #$FF                        A:01 X:00 Y:03 P:26 SP:FD PPU:  0, 33 CYC:11",
+            result[2]
+        );
+    }
+
+    #[test]
+    fn test_format_mem_access() {
+        let mut cpu = CPU::new();
+        cpu.halt_on_brk = true;
+        // LDA ($33),Y
+        cpu.mem_write(0x64, LDA::INDIRECT_Y::VALUE);
+        cpu.mem_write(0x65, 0x33);
+        // STA $0400,X
+        cpu.mem_write(0x66, STA::ABSOLUTE_X::VALUE);
+        cpu.mem_write(0x67, 0x00);
+        cpu.mem_write(0x68, 0x04);
+        cpu.mem_write(0x69, BRK::NONE_ADDRESSING::VALUE);
+
+        // Pointer at 0x33 to 0x0400, value at 0x0400 + Y
+        cpu.mem_write(0x33, 0x00);
+        cpu.mem_write(0x34, 0x04);
+        cpu.mem_write(0x0405, 0xAA);
+
+        cpu.counter = 0x64;
+        cpu.register_x = 1;
+        cpu.register_y = 5;
+
+        let mut result: Vec<String> = vec![];
+        cpu.run_with_callback(|cpu| {
+            result.push(trace(cpu));
+        }).unwrap();
+
+        assert_eq!(
+            "0064  B1 33     LDA ($33),Y = 0400 @ 0405 = AA  A:00 X:01 Y:05 P:24 SP:FD PPU:  0,  0 CYC:0",
+            result[0]
+        );
+        assert_eq!(
+            "0066  9D 00 04  STA $0400,X @ 0401 = 00         A:AA X:01 Y:05 P:A4 SP:FD PPU:  0, 15 CYC:5",
+            result[1]
+        );
+    }
+
+    // Needs nestest.nes and nestest.log from
+    // https://www.qmtpro.com/~nes/misc/ in the working directory
+    #[test]
+    #[ignore]
+    fn test_nestest_log() {
+        use crate::cartridge::Rom;
+
+        let log = std::fs::read_to_string("nestest.log").unwrap();
+        let mut cpu = CPU::new();
+        cpu.load_rom(Rom::load("nestest.nes").unwrap()).unwrap();
+        cpu.reset_interrupt();
+        // Automated mode starts at 0xC000, after the 7 cycle reset
+        cpu.counter = 0xC000;
+        cpu.cycles = 7;
+        cpu.bus.tick(7);
+        cpu.halt_on_brk = true;
+
+        let mut expected = log.lines();
+        cpu.run_with_callback(|cpu| {
+            match expected.next() {
+                Some(line) => assert_eq!(line, trace(cpu)),
+                // Matched the whole log, stop on a halting BRK
+                None => {
+                    cpu.mem_write(0x0200, 0x00);
+                    cpu.counter = 0x0200;
+                }
+            }
+        }).unwrap();
+    }
+}
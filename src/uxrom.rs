@@ -0,0 +1,115 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bus_conflict, chr_memory, has_bus_conflicts, Mapper, PRG_ROM};
+
+const PRG_BANK_SIZE: usize = 16 * 1024;
+
+/// Mapper 2, a 16 KiB PRG bank at 0x8000 selected by any write to PRG
+/// space, with the last bank fixed at 0xC000. CHR is 8 KiB of RAM.
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    pub bank: u8,
+}
+
+impl Uxrom {
+
+    pub fn new(rom: Rom) -> Self {
+        let bus_conflicts = has_bus_conflicts(&rom, true);
+        let (chr, chr_ram) = chr_memory(rom.chr_rom);
+
+        Uxrom {
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_ram,
+            mirroring: rom.mirroring,
+            bus_conflicts,
+            bank: 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        if addr < PRG_ROM || self.prg_rom.is_empty() {
+            return 0;
+        }
+
+        let bank_count = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let bank = match addr {
+            0x8000 ..= 0xBFFF => self.bank as usize % bank_count,
+            _ => bank_count - 1,
+        };
+        let offset = (addr - PRG_ROM) as usize % PRG_BANK_SIZE;
+
+        return self.prg_rom[(bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()];
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= PRG_ROM {
+            self.bank = bus_conflict(data, self.cpu_peek(addr), self.bus_conflicts);
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        return self.chr[addr as usize % self.chr.len()];
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{numbered_banks, RomBuilder};
+
+    fn uxrom(submapper: u8) -> Uxrom {
+        return Uxrom::new(RomBuilder::new(2)
+            .submapper(submapper)
+            .prg_rom(numbered_banks(8, PRG_BANK_SIZE))
+            .build());
+    }
+
+    #[test]
+    fn test_uxrom() {
+        let mut uxrom = uxrom(0);
+        assert_eq!(uxrom.cpu_peek(0x8000), 0);
+        assert_eq!(uxrom.cpu_peek(0xC000), 7);
+
+        uxrom.cpu_write(0xC001, 3);
+        assert_eq!(uxrom.cpu_peek(0x8000), 3);
+        assert_eq!(uxrom.cpu_peek(0xC000), 7);
+
+        // CHR RAM
+        uxrom.ppu_write(0x1000, 0x55);
+        assert_eq!(uxrom.ppu_read(0x1000), 0x55);
+    }
+
+    #[test]
+    fn test_uxrom_bus_conflicts() {
+        // The fixed bank holds 7, ANDed with the write
+        let mut uxrom = uxrom(0);
+        uxrom.cpu_write(0xC000, 0x05);
+        assert_eq!(uxrom.bank, 0x05 & 0x07);
+        uxrom.cpu_write(0xC000, 0x0A);
+        assert_eq!(uxrom.bank, 0x02);
+    }
+
+    #[test]
+    fn test_uxrom_submapper_without_bus_conflicts() {
+        let mut uxrom = uxrom(1);
+        uxrom.cpu_write(0xC000, 0x0A);
+        assert_eq!(uxrom.bank, 0x0A);
+    }
+}